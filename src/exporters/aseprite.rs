@@ -0,0 +1,260 @@
+use super::{
+    is_trimmed, named_frames, page_stem, reject_rotated, require_png, AtlasExporter, ExportedFile,
+    Playback,
+};
+use crate::spritesheet::SpriteSheet;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Rect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    filename: String,
+    frame: Rect,
+    rotated: bool,
+    trimmed: bool,
+    /// Where the cropped frame sits in the original frame
+    sprite_source_size: Rect,
+    source_size: Size,
+    /// How long the frame is shown for, in milliseconds
+    duration: u32,
+}
+
+#[derive(Serialize)]
+struct FrameTag {
+    name: String,
+    /// The index of the first frame of the animation in `frames`
+    from: usize,
+    /// The index of the last frame of the animation in `frames`
+    to: usize,
+    direction: String,
+    /// How many times the animation plays, left out when it loops forever
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    app: String,
+    version: String,
+    image: String,
+    format: String,
+    size: Size,
+    scale: String,
+    frame_tags: Vec<FrameTag>,
+    layers: Vec<()>,
+    slices: Vec<()>,
+}
+
+#[derive(Serialize)]
+struct Atlas {
+    frames: Vec<Frame>,
+    meta: Meta,
+}
+
+/// Writes the JSON Aseprite exports with `--data`, with a frame tag for every animation.
+pub struct AsepriteExporter {
+    pub playback: Playback,
+}
+
+impl AtlasExporter for AsepriteExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "Aseprite JSON")?;
+
+        reject_rotated(&frames, "Aseprite JSON")?;
+
+        let mut files = vec![];
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            // Aseprite only writes RGBA8888 or indexed sheets
+            require_png(page, "Aseprite JSON")?;
+
+            let page_frames = frames
+                .iter()
+                .filter(|f| f.frame.page == page_index)
+                .collect::<Vec<_>>();
+
+            // Frames are sorted by animation, so each animation on the page is one run of frames
+            let mut frame_tags: Vec<FrameTag> = vec![];
+            for (i, f) in page_frames.iter().enumerate() {
+                match frame_tags.last_mut() {
+                    Some(tag) if tag.name == f.animation => tag.to = i,
+                    _ => frame_tags.push(FrameTag {
+                        name: f.animation.to_string(),
+                        from: i,
+                        to: i,
+                        direction: "forward".to_string(),
+                        repeat: (!self.playback.looping(f.animation)).then(|| "1".to_string()),
+                    }),
+                }
+            }
+
+            let atlas = Atlas {
+                frames: page_frames
+                    .iter()
+                    .map(|f| {
+                        let frame = f.frame;
+                        Frame {
+                            filename: f.name.clone(),
+                            frame: Rect {
+                                x: frame.x,
+                                y: frame.y,
+                                w: frame.width,
+                                h: frame.height,
+                            },
+                            rotated: false,
+                            trimmed: is_trimmed(frame),
+                            sprite_source_size: Rect {
+                                x: frame.top_left_offset_x,
+                                y: frame.top_left_offset_y,
+                                w: frame.width,
+                                h: frame.height,
+                            },
+                            source_size: Size {
+                                w: frame.original_width,
+                                h: frame.original_height,
+                            },
+                            duration: self.playback.frame_duration_ms(f.animation),
+                        }
+                    })
+                    .collect(),
+                meta: Meta {
+                    app: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    image: page.image.clone(),
+                    format: "RGBA8888".to_string(),
+                    size: Size {
+                        w: page.width,
+                        h: page.height,
+                    },
+                    scale: "1".to_string(),
+                    frame_tags,
+                    layers: vec![],
+                    slices: vec![],
+                },
+            };
+
+            files.push(ExportedFile {
+                name: format!("{}_aseprite.json", page_stem(page)),
+                contents: serde_json::to_string_pretty(&atlas).unwrap(),
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn export(exporter: &AsepriteExporter, sheet: &SpriteSheet) -> Vec<(String, Value)> {
+        exporter
+            .export(sheet)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.name, serde_json::from_str(&f.contents).unwrap()))
+            .collect()
+    }
+
+    fn exporter() -> AsepriteExporter {
+        AsepriteExporter {
+            playback: playback(),
+        }
+    }
+
+    #[test]
+    fn animations_split_across_pages_are_tagged_on_each_page() {
+        let mut exporter = exporter();
+        exporter.playback.no_loop.insert("walk".to_string());
+        exporter
+            .playback
+            .animation_fps
+            .insert("idle".to_string(), 4.0);
+        let files = export(&exporter, &two_page_sheet());
+        let names = files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Hero_0_aseprite.json", "Hero_1_aseprite.json"]);
+
+        assert_eq!(
+            files[0].1["meta"]["frameTags"],
+            json!([
+                { "name": "walk", "from": 0, "to": 1, "direction": "forward", "repeat": "1" }
+            ])
+        );
+        assert_eq!(
+            files[1].1["meta"]["frameTags"],
+            json!([
+                { "name": "idle", "from": 0, "to": 0, "direction": "forward" },
+                { "name": "walk", "from": 1, "to": 1, "direction": "forward", "repeat": "1" }
+            ])
+        );
+
+        let frames = files[1].1["frames"].as_array().unwrap();
+        assert_eq!(frames[0]["filename"], "idle_0000");
+        assert_eq!(frames[0]["duration"], 250);
+        assert_eq!(frames[1]["filename"], "walk_0002");
+        assert_eq!(frames[1]["duration"], 100);
+    }
+
+    #[test]
+    fn trimmed_frames_say_where_they_sit_in_the_original() {
+        let files = export(&exporter(), &trimmed_sheet());
+        assert_eq!(files[0].0, "Hero_aseprite.json");
+        let atlas = &files[0].1;
+        assert_eq!(
+            atlas["frames"][0],
+            json!({
+                "filename": "idle_0000",
+                "frame": { "x": 0, "y": 0, "w": 10, "h": 12 },
+                "rotated": false,
+                "trimmed": true,
+                "spriteSourceSize": { "x": 2, "y": 1, "w": 10, "h": 12 },
+                "sourceSize": { "w": 16, "h": 16 },
+                "duration": 100
+            })
+        );
+        assert_eq!(
+            atlas["meta"]["frameTags"],
+            json!([
+                { "name": "idle", "from": 0, "to": 1, "direction": "forward" },
+                { "name": "run", "from": 2, "to": 2, "direction": "forward" }
+            ])
+        );
+        assert_eq!(atlas["meta"]["image"], "Hero.png");
+        assert_eq!(atlas["meta"]["format"], "RGBA8888");
+        assert_eq!(atlas["meta"]["size"], json!({ "w": 64, "h": 32 }));
+    }
+
+    #[test]
+    fn pages_that_arent_pngs_are_refused() {
+        for format in ["rgba4444", "rgb565", "rgba5551"] {
+            let error = export_error(&exporter(), &raw_pixels_sheet(format));
+            assert!(error.contains(format), "{error}");
+            assert!(error.contains("--pixel-format"), "{error}");
+        }
+        let error = export_error(&exporter(), &compressed_sheet());
+        assert!(error.contains("--texture-format"), "{error}");
+        let error = export_error(&exporter(), &rotated_sheet());
+        assert!(error.contains("--allow-rotation"), "{error}");
+        let error = export_error(&exporter(), &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+    }
+}
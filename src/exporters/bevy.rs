@@ -0,0 +1,226 @@
+use super::{
+    named_frames, quote_string, reject_raw_pixels, reject_rotated, AtlasExporter, ExportedFile,
+    Playback,
+};
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes a RON file with a Bevy `TextureAtlasLayout` for every page and the frames of each animation.
+pub struct BevyExporter {
+    pub playback: Playback,
+}
+
+impl AtlasExporter for BevyExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "Bevy texture atlases")?;
+
+        reject_rotated(&frames, "Bevy texture atlases")?;
+        for page in sheet.pages.iter() {
+            reject_raw_pixels(page, "Bevy texture atlases")?;
+        }
+
+        // Frames are indexed in the order they're listed on their page, which keeps each animation in one run per page
+        let mut indices = vec![0; frames.len()];
+        let mut out = String::new();
+        writeln!(out, "(").unwrap();
+        writeln!(out, "    layouts: [").unwrap();
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            writeln!(out, "        (").unwrap();
+            writeln!(out, "            image: {},", quote_string(&page.image)).unwrap();
+            writeln!(out, "            size: ({}, {}),", page.width, page.height).unwrap();
+
+            let mut textures = String::new();
+            let mut trims = String::new();
+            let mut index = 0;
+            for (i, f) in frames.iter().enumerate() {
+                let frame = f.frame;
+                if frame.page != page_index {
+                    continue;
+                }
+                indices[i] = index;
+                index += 1;
+
+                writeln!(
+                    textures,
+                    "                (min: ({}, {}), max: ({}, {})),",
+                    frame.x,
+                    frame.y,
+                    frame.x + frame.width,
+                    frame.y + frame.height
+                )
+                .unwrap();
+                writeln!(
+                    trims,
+                    "                (offset: ({}, {}), original_size: ({}, {})),",
+                    frame.top_left_offset_x,
+                    frame.top_left_offset_y,
+                    frame.original_width,
+                    frame.original_height
+                )
+                .unwrap();
+            }
+
+            writeln!(out, "            textures: [").unwrap();
+            out.push_str(&textures);
+            writeln!(out, "            ],").unwrap();
+            // Bevy doesn't know about trimming, so this says where each texture sits in its original frame
+            writeln!(out, "            trims: [").unwrap();
+            out.push_str(&trims);
+            writeln!(out, "            ],").unwrap();
+            writeln!(out, "        ),").unwrap();
+        }
+        writeln!(out, "    ],").unwrap();
+
+        writeln!(out, "    animations: {{").unwrap();
+        for animation in sheet.sprites.keys() {
+            // Split the animation into runs of consecutive indices on the same page, with the end excluded
+            let mut ranges: Vec<(usize, usize, usize)> = vec![];
+            for (i, f) in frames.iter().enumerate() {
+                if f.animation != animation {
+                    continue;
+                }
+                match ranges.last_mut() {
+                    Some((page, _, end)) if *page == f.frame.page && *end == indices[i] => {
+                        *end += 1
+                    }
+                    _ => ranges.push((f.frame.page, indices[i], indices[i] + 1)),
+                }
+            }
+
+            writeln!(out, "        {}: (", quote_string(animation)).unwrap();
+            writeln!(out, "            fps: {:?},", self.playback.fps(animation)).unwrap();
+            writeln!(
+                out,
+                "            looping: {},",
+                self.playback.looping(animation)
+            )
+            .unwrap();
+            writeln!(out, "            frames: [").unwrap();
+            for (page, start, end) in ranges {
+                writeln!(
+                    out,
+                    "                (page: {}, start: {}, end: {}),",
+                    page, start, end
+                )
+                .unwrap();
+            }
+            writeln!(out, "            ],").unwrap();
+            writeln!(out, "        ),").unwrap();
+        }
+        writeln!(out, "    }},").unwrap();
+        writeln!(out, ")").unwrap();
+
+        Ok(vec![ExportedFile {
+            name: format!("{}.ron", sheet.name),
+            contents: out,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    fn exporter() -> BevyExporter {
+        BevyExporter {
+            playback: playback(),
+        }
+    }
+
+    #[test]
+    fn animations_are_split_into_runs_on_each_page() {
+        let (name, contents) = export_one(&exporter(), &two_page_sheet());
+        assert_eq!(name, "Hero.ron");
+        assert_eq!(
+            contents,
+            r#"(
+    layouts: [
+        (
+            image: "Hero_0.png",
+            size: (16, 8),
+            textures: [
+                (min: (0, 0), max: (8, 8)),
+                (min: (8, 0), max: (16, 8)),
+            ],
+            trims: [
+                (offset: (0, 0), original_size: (8, 8)),
+                (offset: (0, 0), original_size: (8, 8)),
+            ],
+        ),
+        (
+            image: "Hero_1.png",
+            size: (16, 8),
+            textures: [
+                (min: (0, 0), max: (6, 6)),
+                (min: (8, 0), max: (16, 8)),
+            ],
+            trims: [
+                (offset: (1, 1), original_size: (8, 8)),
+                (offset: (0, 0), original_size: (8, 8)),
+            ],
+        ),
+    ],
+    animations: {
+        "idle": (
+            fps: 10.0,
+            looping: true,
+            frames: [
+                (page: 1, start: 0, end: 1),
+            ],
+        ),
+        "walk": (
+            fps: 10.0,
+            looping: true,
+            frames: [
+                (page: 0, start: 0, end: 2),
+                (page: 1, start: 1, end: 2),
+            ],
+        ),
+    },
+)
+"#
+        );
+    }
+
+    #[test]
+    fn animations_that_come_back_to_a_page_start_a_new_run() {
+        let frame = |page, x| frame(page, [x, 0], [8, 8], [8, 8], [0, 0]);
+        let sheet = sheet(
+            &[("Hero_0.png", 24, 8), ("Hero_1.png", 8, 8)],
+            vec![
+                ("idle", vec![frame(0, 0)]),
+                ("walk", vec![frame(0, 8), frame(1, 0), frame(0, 16)]),
+            ],
+        );
+        let mut exporter = exporter();
+        exporter.playback.no_loop.insert("walk".to_string());
+        let (_, contents) = export_one(&exporter, &sheet);
+        assert!(contents.contains(
+            r#"        "walk": (
+            fps: 10.0,
+            looping: false,
+            frames: [
+                (page: 0, start: 1, end: 2),
+                (page: 1, start: 0, end: 1),
+                (page: 0, start: 2, end: 3),
+            ],
+        ),
+"#
+        ));
+        assert!(contents.contains("(page: 0, start: 0, end: 1),\n"));
+    }
+
+    #[test]
+    fn sheets_bevy_cant_load_are_refused() {
+        let error = export_error(&exporter(), &raw_pixels_sheet("rgba4444"));
+        assert!(error.contains("--pixel-format"), "{error}");
+        let error = export_error(&exporter(), &rotated_sheet());
+        assert!(error.contains("--allow-rotation"), "{error}");
+        let error = export_error(&exporter(), &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+
+        let (_, contents) = export_one(&exporter(), &compressed_sheet());
+        assert!(contents.contains("image: \"Hero.ktx2\","));
+    }
+}
@@ -0,0 +1,296 @@
+use super::{escape_xml, image_pixel_format, named_frames, page_stem, AtlasExporter, ExportedFile};
+use crate::pixel_format::PixelFormat;
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes a cocos2d sprite frame property list, in format 3.
+pub struct Cocos2dExporter;
+
+/// Writes a key and a string value of a plist dictionary.
+fn key_string(out: &mut String, indent: &str, key: &str, value: &str) {
+    writeln!(out, "{}<key>{}</key>", indent, escape_xml(key)).unwrap();
+    writeln!(out, "{}<string>{}</string>", indent, escape_xml(value)).unwrap();
+}
+
+/// Writes a key and a boolean value of a plist dictionary.
+fn key_bool(out: &mut String, indent: &str, key: &str, value: bool) {
+    writeln!(out, "{}<key>{}</key>", indent, key).unwrap();
+    writeln!(out, "{}<{}/>", indent, value).unwrap();
+}
+
+impl AtlasExporter for Cocos2dExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "cocos2d plists")?;
+
+        let mut files = vec![];
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            let mut out = String::new();
+            writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+            writeln!(
+                out,
+                r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#
+            )
+            .unwrap();
+            writeln!(out, r#"<plist version="1.0">"#).unwrap();
+            writeln!(out, "<dict>").unwrap();
+            writeln!(out, "    <key>frames</key>").unwrap();
+            writeln!(out, "    <dict>").unwrap();
+
+            for f in frames.iter().filter(|f| f.frame.page == page_index) {
+                let frame = f.frame;
+                // cocos2d offsets the centre of the cropped frame from the centre of the original, with y up
+                let offset_x = frame.top_left_offset_x as f32 + frame.width as f32 / 2.0
+                    - frame.original_width as f32 / 2.0;
+                let offset_y = frame.original_height as f32 / 2.0
+                    - (frame.top_left_offset_y as f32 + frame.height as f32 / 2.0);
+
+                let indent = "            ";
+                writeln!(out, "        <key>{}</key>", escape_xml(&f.name)).unwrap();
+                writeln!(out, "        <dict>").unwrap();
+                writeln!(out, "{}<key>aliases</key>", indent).unwrap();
+                writeln!(out, "{}<array/>", indent).unwrap();
+                key_string(
+                    &mut out,
+                    indent,
+                    "spriteOffset",
+                    &format!("{{{},{}}}", offset_x, offset_y),
+                );
+                key_string(
+                    &mut out,
+                    indent,
+                    "spriteSize",
+                    &format!("{{{},{}}}", frame.width, frame.height),
+                );
+                key_string(
+                    &mut out,
+                    indent,
+                    "spriteSourceSize",
+                    &format!("{{{},{}}}", frame.original_width, frame.original_height),
+                );
+                // The size is before rotation, like the sprite sheet JSON
+                key_string(
+                    &mut out,
+                    indent,
+                    "textureRect",
+                    &format!(
+                        "{{{{{},{}}},{{{},{}}}}}",
+                        frame.x, frame.y, frame.width, frame.height
+                    ),
+                );
+                key_bool(&mut out, indent, "textureRotated", frame.rotated);
+                writeln!(out, "        </dict>").unwrap();
+            }
+
+            writeln!(out, "    </dict>").unwrap();
+            writeln!(out, "    <key>metadata</key>").unwrap();
+            writeln!(out, "    <dict>").unwrap();
+            let indent = "        ";
+            writeln!(out, "{}<key>format</key>", indent).unwrap();
+            writeln!(out, "{}<integer>3</integer>", indent).unwrap();
+            let format = match image_pixel_format(page, "cocos2d plists")? {
+                None => "RGBA8888",
+                Some(PixelFormat::Rgba4444) => "RGBA4444",
+                Some(PixelFormat::Rgb565) => "RGB565",
+                Some(PixelFormat::Rgba5551) => "RGB5A1",
+            };
+            key_string(&mut out, indent, "pixelFormat", format);
+            key_bool(&mut out, indent, "premultiplyAlpha", sheet.premultiplied);
+            key_string(&mut out, indent, "realTextureFileName", &page.image);
+            key_string(
+                &mut out,
+                indent,
+                "size",
+                &format!("{{{},{}}}", page.width, page.height),
+            );
+            key_string(&mut out, indent, "textureFileName", &page.image);
+            writeln!(out, "    </dict>").unwrap();
+            writeln!(out, "</dict>").unwrap();
+            writeln!(out, "</plist>").unwrap();
+
+            files.push(ExportedFile {
+                name: format!("{}.plist", page_stem(page)),
+                contents: out,
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    /// The frame dictionary cocos2d expects for one frame.
+    fn frame_dict(name: &str, offset: &str, size: &str, source: &str, rect: &str) -> String {
+        format!(
+            r#"        <key>{name}</key>
+        <dict>
+            <key>aliases</key>
+            <array/>
+            <key>spriteOffset</key>
+            <string>{offset}</string>
+            <key>spriteSize</key>
+            <string>{size}</string>
+            <key>spriteSourceSize</key>
+            <string>{source}</string>
+            <key>textureRect</key>
+            <string>{rect}</string>
+"#
+        )
+    }
+
+    #[test]
+    fn offsets_are_from_the_centre_with_y_up() {
+        let (name, contents) = export_one(&Cocos2dExporter, &trimmed_sheet());
+        assert_eq!(name, "Hero.plist");
+        assert_eq!(
+            contents,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>frames</key>
+    <dict>
+        <key>idle_0000</key>
+        <dict>
+            <key>aliases</key>
+            <array/>
+            <key>spriteOffset</key>
+            <string>{-1,1}</string>
+            <key>spriteSize</key>
+            <string>{10,12}</string>
+            <key>spriteSourceSize</key>
+            <string>{16,16}</string>
+            <key>textureRect</key>
+            <string>{{0,0},{10,12}}</string>
+            <key>textureRotated</key>
+            <false/>
+        </dict>
+        <key>idle_0001</key>
+        <dict>
+            <key>aliases</key>
+            <array/>
+            <key>spriteOffset</key>
+            <string>{0,0}</string>
+            <key>spriteSize</key>
+            <string>{16,16}</string>
+            <key>spriteSourceSize</key>
+            <string>{16,16}</string>
+            <key>textureRect</key>
+            <string>{{10,0},{16,16}}</string>
+            <key>textureRotated</key>
+            <false/>
+        </dict>
+        <key>run_0000</key>
+        <dict>
+            <key>aliases</key>
+            <array/>
+            <key>spriteOffset</key>
+            <string>{1,-2}</string>
+            <key>spriteSize</key>
+            <string>{8,6}</string>
+            <key>spriteSourceSize</key>
+            <string>{12,10}</string>
+            <key>textureRect</key>
+            <string>{{26,0},{8,6}}</string>
+            <key>textureRotated</key>
+            <false/>
+        </dict>
+    </dict>
+    <key>metadata</key>
+    <dict>
+        <key>format</key>
+        <integer>3</integer>
+        <key>pixelFormat</key>
+        <string>RGBA8888</string>
+        <key>premultiplyAlpha</key>
+        <false/>
+        <key>realTextureFileName</key>
+        <string>Hero.png</string>
+        <key>size</key>
+        <string>{64,32}</string>
+        <key>textureFileName</key>
+        <string>Hero.png</string>
+    </dict>
+</dict>
+</plist>
+"#
+        );
+    }
+
+    #[test]
+    fn rotated_frames_keep_their_unrotated_size() {
+        let (_, contents) = export_one(&Cocos2dExporter, &rotated_sheet());
+        let frame = frame_dict("spin_0000", "{1,1}", "{10,4}", "{12,8}", "{{2,3},{10,4}}");
+        let rotated = "            <key>textureRotated</key>\n            <true/>\n";
+        assert!(contents.contains(&(frame + rotated)), "{contents}");
+    }
+
+    #[test]
+    fn every_page_gets_its_own_file() {
+        let files = Cocos2dExporter.export(&two_page_sheet()).unwrap();
+        let names = files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Hero_0.plist", "Hero_1.plist"]);
+
+        let untrimmed = |name, x| {
+            frame_dict(
+                name,
+                "{0,0}",
+                "{8,8}",
+                "{8,8}",
+                &format!("{{{{{},0}},{{8,8}}}}", x),
+            )
+        };
+        for (file, image, frames) in [
+            (
+                &files[0],
+                "Hero_0.png",
+                vec![untrimmed("walk_0000", 0), untrimmed("walk_0001", 8)],
+            ),
+            (
+                &files[1],
+                "Hero_1.png",
+                vec![
+                    frame_dict("idle_0000", "{0,0}", "{6,6}", "{8,8}", "{{0,0},{6,6}}"),
+                    untrimmed("walk_0002", 8),
+                ],
+            ),
+        ] {
+            assert_eq!(
+                file.contents.matches("<key>textureRect</key>").count(),
+                frames.len()
+            );
+            for frame in frames {
+                assert!(file.contents.contains(&frame), "{}", file.contents);
+            }
+            let texture = format!(
+                "<key>textureFileName</key>\n        <string>{}</string>",
+                image
+            );
+            assert!(file.contents.contains(&texture));
+        }
+    }
+
+    #[test]
+    fn the_format_is_the_page_image_format() {
+        for (format, expected) in [
+            ("rgba4444", "RGBA4444"),
+            ("rgb565", "RGB565"),
+            ("rgba5551", "RGB5A1"),
+        ] {
+            let (_, contents) = export_one(&Cocos2dExporter, &raw_pixels_sheet(format));
+            let pixel_format = format!(
+                "<key>pixelFormat</key>\n        <string>{}</string>",
+                expected
+            );
+            assert!(contents.contains(&pixel_format), "{contents}");
+        }
+
+        let error = export_error(&Cocos2dExporter, &compressed_sheet());
+        assert!(error.contains("--texture-format"), "{error}");
+        let error = export_error(&Cocos2dExporter, &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+    }
+}
@@ -0,0 +1,224 @@
+use super::{
+    is_trimmed, named_frames, quote_string, reject_raw_pixels, reject_rotated, AtlasExporter,
+    ExportedFile, Playback,
+};
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes a Godot 4 `SpriteFrames` resource, with an `AtlasTexture` for every frame.
+pub struct GodotExporter {
+    pub playback: Playback,
+}
+
+impl AtlasExporter for GodotExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "Godot SpriteFrames")?;
+
+        reject_rotated(&frames, "Godot SpriteFrames")?;
+        for page in sheet.pages.iter() {
+            reject_raw_pixels(page, "Godot SpriteFrames")?;
+        }
+
+        let mut out = String::new();
+        let load_steps = sheet.pages.len() + frames.len() + 1;
+        writeln!(
+            out,
+            r#"[gd_resource type="SpriteFrames" load_steps={} format=3]"#,
+            load_steps
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        // Paths without `res://` are relative to the resource, so the sheet can go anywhere in the project
+        for (i, page) in sheet.pages.iter().enumerate() {
+            writeln!(
+                out,
+                r#"[ext_resource type="Texture2D" path={} id="{}"]"#,
+                quote_string(&page.image),
+                i + 1
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+
+        for (i, f) in frames.iter().enumerate() {
+            let frame = f.frame;
+            writeln!(
+                out,
+                r#"[sub_resource type="AtlasTexture" id="AtlasTexture_{}"]"#,
+                i + 1
+            )
+            .unwrap();
+            writeln!(out, r#"atlas = ExtResource("{}")"#, frame.page + 1).unwrap();
+            writeln!(
+                out,
+                "region = Rect2({}, {}, {}, {})",
+                frame.x, frame.y, frame.width, frame.height
+            )
+            .unwrap();
+            // The position is the space cropped off the top left, and the size is the total cropped off each axis
+            if is_trimmed(frame) {
+                writeln!(
+                    out,
+                    "margin = Rect2({}, {}, {}, {})",
+                    frame.top_left_offset_x,
+                    frame.top_left_offset_y,
+                    frame.original_width - frame.width,
+                    frame.original_height - frame.height
+                )
+                .unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+
+        writeln!(out, "[resource]").unwrap();
+        let mut animations = vec![];
+        for animation in sheet.sprites.keys() {
+            let textures = frames
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| f.animation == animation)
+                .map(|(i, _)| {
+                    format!(
+                        "{{\n\"duration\": 1.0,\n\"texture\": SubResource(\"AtlasTexture_{}\")\n}}",
+                        i + 1
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            animations.push(format!(
+                "{{\n\"frames\": [{}],\n\"loop\": {},\n\"name\": &{},\n\"speed\": {:?}\n}}",
+                textures.join(", "),
+                self.playback.looping(animation),
+                quote_string(animation),
+                self.playback.fps(animation)
+            ));
+        }
+        writeln!(out, "animations = [{}]", animations.join(", ")).unwrap();
+
+        Ok(vec![ExportedFile {
+            name: format!("{}.tres", sheet.name),
+            contents: out,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::super::AnimationFps;
+    use super::*;
+
+    #[test]
+    fn trimmed_frames_have_a_margin() {
+        let exporter = GodotExporter {
+            playback: playback(),
+        };
+        let (name, contents) = export_one(&exporter, &trimmed_sheet());
+        assert_eq!(name, "Hero.tres");
+
+        // A page, three frames and the resource itself
+        assert!(contents.starts_with(
+            "[gd_resource type=\"SpriteFrames\" load_steps=5 format=3]\n\n\
+             [ext_resource type=\"Texture2D\" path=\"Hero.png\" id=\"1\"]\n"
+        ));
+        assert!(contents.contains(
+            "[sub_resource type=\"AtlasTexture\" id=\"AtlasTexture_1\"]\n\
+             atlas = ExtResource(\"1\")\n\
+             region = Rect2(0, 0, 10, 12)\n\
+             margin = Rect2(2, 1, 6, 4)\n\n"
+        ));
+        assert!(contents.contains(
+            "[sub_resource type=\"AtlasTexture\" id=\"AtlasTexture_2\"]\n\
+             atlas = ExtResource(\"1\")\n\
+             region = Rect2(10, 0, 16, 16)\n\n"
+        ));
+        assert!(contents.contains("region = Rect2(26, 0, 8, 6)\nmargin = Rect2(3, 4, 4, 4)\n"));
+
+        // Two pages and four frames, each pointing at its own page
+        let (_, contents) = export_one(&exporter, &two_page_sheet());
+        assert!(contents.starts_with(
+            "[gd_resource type=\"SpriteFrames\" load_steps=7 format=3]\n\n\
+             [ext_resource type=\"Texture2D\" path=\"Hero_0.png\" id=\"1\"]\n\
+             [ext_resource type=\"Texture2D\" path=\"Hero_1.png\" id=\"2\"]\n"
+        ));
+        let atlases = contents
+            .lines()
+            .filter(|l| l.starts_with("atlas = "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            atlases,
+            [
+                "atlas = ExtResource(\"2\")",
+                "atlas = ExtResource(\"1\")",
+                "atlas = ExtResource(\"1\")",
+                "atlas = ExtResource(\"2\")"
+            ]
+        );
+    }
+
+    #[test]
+    fn animations_play_at_their_own_speed() {
+        let mut playback = playback();
+        let run = "run=24".parse::<AnimationFps>().unwrap();
+        playback.animation_fps.insert(run.animation, run.fps);
+        playback.no_loop.insert("idle".to_string());
+        let sheet = trimmed_sheet();
+        playback.check_animations(&sheet).unwrap();
+
+        let (_, contents) = export_one(&GodotExporter { playback }, &sheet);
+        assert!(contents.ends_with(
+            "animations = [{\n\
+             \"frames\": [{\n\
+             \"duration\": 1.0,\n\
+             \"texture\": SubResource(\"AtlasTexture_1\")\n\
+             }, {\n\
+             \"duration\": 1.0,\n\
+             \"texture\": SubResource(\"AtlasTexture_2\")\n\
+             }],\n\
+             \"loop\": false,\n\
+             \"name\": &\"idle\",\n\
+             \"speed\": 10.0\n\
+             }, {\n\
+             \"frames\": [{\n\
+             \"duration\": 1.0,\n\
+             \"texture\": SubResource(\"AtlasTexture_3\")\n\
+             }],\n\
+             \"loop\": true,\n\
+             \"name\": &\"run\",\n\
+             \"speed\": 24.0\n\
+             }]\n"
+        ));
+    }
+
+    #[test]
+    fn unknown_animations_are_an_error() {
+        let sheet = trimmed_sheet();
+        let mut speeds = playback();
+        speeds.animation_fps.insert("runn".to_string(), 24.0);
+        let error = speeds.check_animations(&sheet).unwrap_err();
+        assert!(error.contains("'runn'"), "{error}");
+
+        let mut once = playback();
+        once.no_loop.insert("jump".to_string());
+        let error = once.check_animations(&sheet).unwrap_err();
+        assert!(error.contains("'jump'"), "{error}");
+    }
+
+    #[test]
+    fn sheets_godot_cant_load_are_refused() {
+        let exporter = GodotExporter {
+            playback: playback(),
+        };
+        let error = export_error(&exporter, &raw_pixels_sheet("rgb565"));
+        assert!(error.contains("--pixel-format"), "{error}");
+        let error = export_error(&exporter, &rotated_sheet());
+        assert!(error.contains("--allow-rotation"), "{error}");
+        let error = export_error(&exporter, &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+
+        // Godot imports compressed textures, so those are fine
+        let (_, contents) = export_one(&exporter, &compressed_sheet());
+        assert!(contents.contains("path=\"Hero.ktx2\""));
+    }
+}
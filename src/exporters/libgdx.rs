@@ -0,0 +1,194 @@
+use super::{image_pixel_format, named_frames, reject_rotated, AtlasExporter, ExportedFile};
+use crate::pixel_format::PixelFormat;
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes a libGDX texture atlas, with each animation as a region name and the frame as its index.
+pub struct LibGdxExporter;
+
+impl AtlasExporter for LibGdxExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "libGDX atlases")?;
+
+        // libGDX turns sprites counter-clockwise, the opposite way to the packers here
+        reject_rotated(&frames, "libGDX atlases")?;
+
+        // Lines are trimmed and anything with a colon is read as a field, so those names would break the atlas
+        if let Some(f) = frames
+            .iter()
+            .find(|f| f.animation.trim() != f.animation || f.animation.contains(':'))
+        {
+            return Err(format!(
+                "libGDX atlases can't have region names with a colon or spaces around them, like {:?}",
+                f.animation
+            ));
+        }
+
+        let mut out = String::new();
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            writeln!(out).unwrap();
+            writeln!(out, "{}", page.image).unwrap();
+            writeln!(out, "size: {}, {}", page.width, page.height).unwrap();
+            let format = match image_pixel_format(page, "libGDX atlases")? {
+                None => "RGBA8888",
+                Some(PixelFormat::Rgba4444) => "RGBA4444",
+                Some(PixelFormat::Rgb565) => "RGB565",
+                Some(PixelFormat::Rgba5551) => {
+                    return Err(
+                        "libGDX atlases have no RGBA5551 format, so they can't be used with --pixel-format rgba5551 and --no-png"
+                            .to_string(),
+                    )
+                }
+            };
+            writeln!(out, "format: {}", format).unwrap();
+            writeln!(out, "filter: Nearest, Nearest").unwrap();
+            writeln!(out, "repeat: none").unwrap();
+
+            for f in frames.iter().filter(|f| f.frame.page == page_index) {
+                let frame = f.frame;
+                // Offsets are measured from the bottom left of the original frame
+                let offset_y = frame.original_height - frame.top_left_offset_y - frame.height;
+
+                writeln!(out, "{}", f.animation).unwrap();
+                writeln!(out, "  rotate: false").unwrap();
+                writeln!(out, "  xy: {}, {}", frame.x, frame.y).unwrap();
+                writeln!(out, "  size: {}, {}", frame.width, frame.height).unwrap();
+                writeln!(
+                    out,
+                    "  orig: {}, {}",
+                    frame.original_width, frame.original_height
+                )
+                .unwrap();
+                writeln!(out, "  offset: {}, {}", frame.top_left_offset_x, offset_y).unwrap();
+                writeln!(out, "  index: {}", f.index).unwrap();
+            }
+        }
+
+        Ok(vec![ExportedFile {
+            name: format!("{}.atlas", sheet.name),
+            contents: out,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    #[test]
+    fn offsets_are_measured_from_the_bottom_left() {
+        let (name, contents) = export_one(&LibGdxExporter, &trimmed_sheet());
+        assert_eq!(name, "Hero.atlas");
+        assert_eq!(
+            contents,
+            r#"
+Hero.png
+size: 64, 32
+format: RGBA8888
+filter: Nearest, Nearest
+repeat: none
+idle
+  rotate: false
+  xy: 0, 0
+  size: 10, 12
+  orig: 16, 16
+  offset: 2, 3
+  index: 0
+idle
+  rotate: false
+  xy: 10, 0
+  size: 16, 16
+  orig: 16, 16
+  offset: 0, 0
+  index: 1
+run
+  rotate: false
+  xy: 26, 0
+  size: 8, 6
+  orig: 12, 10
+  offset: 3, 0
+  index: 0
+"#
+        );
+    }
+
+    #[test]
+    fn every_page_is_in_one_atlas() {
+        let (_, contents) = export_one(&LibGdxExporter, &two_page_sheet());
+        assert_eq!(
+            contents,
+            r#"
+Hero_0.png
+size: 16, 8
+format: RGBA8888
+filter: Nearest, Nearest
+repeat: none
+walk
+  rotate: false
+  xy: 0, 0
+  size: 8, 8
+  orig: 8, 8
+  offset: 0, 0
+  index: 0
+walk
+  rotate: false
+  xy: 8, 0
+  size: 8, 8
+  orig: 8, 8
+  offset: 0, 0
+  index: 1
+
+Hero_1.png
+size: 16, 8
+format: RGBA8888
+filter: Nearest, Nearest
+repeat: none
+idle
+  rotate: false
+  xy: 0, 0
+  size: 6, 6
+  orig: 8, 8
+  offset: 1, 1
+  index: 0
+walk
+  rotate: false
+  xy: 8, 0
+  size: 8, 8
+  orig: 8, 8
+  offset: 0, 0
+  index: 2
+"#
+        );
+    }
+
+    #[test]
+    fn the_format_is_the_page_image_format() {
+        for (format, expected) in [("rgba4444", "RGBA4444"), ("rgb565", "RGB565")] {
+            let (_, contents) = export_one(&LibGdxExporter, &raw_pixels_sheet(format));
+            let page = format!(
+                "\nHero_{}.bin\nsize: 64, 32\nformat: {}\n",
+                format, expected
+            );
+            assert!(contents.starts_with(&page), "{contents}");
+        }
+    }
+
+    #[test]
+    fn sheets_libgdx_cant_describe_are_refused() {
+        let error = export_error(&LibGdxExporter, &rotated_sheet());
+        assert!(error.contains("--allow-rotation"), "{error}");
+        let error = export_error(&LibGdxExporter, &raw_pixels_sheet("rgba5551"));
+        assert!(error.contains("RGBA5551"), "{error}");
+        let error = export_error(&LibGdxExporter, &compressed_sheet());
+        assert!(error.contains("--texture-format"), "{error}");
+        let error = export_error(&LibGdxExporter, &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+
+        let mut sheet = trimmed_sheet();
+        let frames = sheet.sprites.remove("run").unwrap();
+        sheet.sprites.insert("run: fast".to_string(), frames);
+        let error = export_error(&LibGdxExporter, &sheet);
+        assert!(error.contains("run: fast"), "{error}");
+    }
+}
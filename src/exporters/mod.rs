@@ -0,0 +1,480 @@
+pub mod aseprite;
+pub mod bevy;
+pub mod cocos2d;
+pub mod godot;
+pub mod libgdx;
+pub mod sparrow;
+pub mod texture_packer;
+pub mod tiled;
+
+use crate::pixel_format::PixelFormat;
+use crate::spritesheet::{Frame, Page, SpriteSheet};
+use clap::{Parser, ValueEnum};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+/// A file written by an exporter, relative to the sprite sheet JSON.
+pub struct ExportedFile {
+    pub name: String,
+    pub contents: String,
+}
+
+/// Writes the layout of a sprite sheet in a format an engine or framework can load.
+pub trait AtlasExporter {
+    /// Describe the sheet, returning the files to write.
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String>;
+}
+
+/// An engine or framework specific format the sprite sheet layout can be written in
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AtlasFormat {
+    /// TexturePacker JSON with frames keyed by name, for Phaser, PixiJS and others. One file per page.
+    TexturepackerHash,
+    /// TexturePacker JSON with a list of frames. One file per page.
+    TexturepackerArray,
+    /// A libGDX `.atlas` file covering every page. Can't describe rotated frames.
+    Libgdx,
+    /// Starling and Sparrow XML. One file per page.
+    Sparrow,
+    /// A cocos2d `.plist` in format 3. One file per page.
+    Cocos2d,
+    /// A Godot 4 `SpriteFrames` resource with one animation per sprite sheet animation.
+    Godot,
+    /// A RON file with a Bevy `TextureAtlasLayout` for every page and the frame ranges of each animation.
+    Bevy,
+    /// A Tiled `.tsx` image collection tileset with a tile for every frame and animated tiles.
+    Tiled,
+    /// Aseprite `--data` JSON with a frame tag for every animation. One file per page.
+    Aseprite,
+}
+
+impl AtlasFormat {
+    /// Returns the exporter implementation for this format.
+    /// Formats that describe animations play them back according to `playback`.
+    pub fn exporter(&self, playback: &Playback) -> Box<dyn AtlasExporter> {
+        match self {
+            AtlasFormat::TexturepackerHash => {
+                Box::new(texture_packer::TexturePackerExporter { array: false })
+            }
+            AtlasFormat::TexturepackerArray => {
+                Box::new(texture_packer::TexturePackerExporter { array: true })
+            }
+            AtlasFormat::Libgdx => Box::new(libgdx::LibGdxExporter),
+            AtlasFormat::Sparrow => Box::new(sparrow::SparrowExporter),
+            AtlasFormat::Cocos2d => Box::new(cocos2d::Cocos2dExporter),
+            AtlasFormat::Godot => Box::new(godot::GodotExporter {
+                playback: playback.clone(),
+            }),
+            AtlasFormat::Bevy => Box::new(bevy::BevyExporter {
+                playback: playback.clone(),
+            }),
+            AtlasFormat::Tiled => Box::new(tiled::TiledExporter {
+                playback: playback.clone(),
+            }),
+            AtlasFormat::Aseprite => Box::new(aseprite::AsepriteExporter {
+                playback: playback.clone(),
+            }),
+        }
+    }
+}
+
+/// The playback speed of one animation, parsed from `ANIMATION=FPS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationFps {
+    pub animation: String,
+    pub fps: f32,
+}
+
+impl FromStr for AnimationFps {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split on the last `=` so animation names can have one in them
+        match s.rsplit_once('=').map(|(a, fps)| (a, fps.parse::<f32>())) {
+            Some((animation, Ok(fps))) if !animation.is_empty() && fps > 0.0 => Ok(Self {
+                animation: animation.to_string(),
+                fps,
+            }),
+            _ => Err(format!(
+                "Invalid animation speed '{}', expected ANIMATION=FPS with an FPS above 0",
+                s
+            )),
+        }
+    }
+}
+
+/// How fast each animation plays and whether it loops, for formats that describe animations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+    /// The frames per second of animations without their own speed
+    pub fps: f32,
+    /// The frames per second of particular animations
+    pub animation_fps: BTreeMap<String, f32>,
+    /// The animations that play once instead of looping
+    pub no_loop: BTreeSet<String>,
+}
+
+impl Playback {
+    /// The frames per second an animation plays at.
+    pub fn fps(&self, animation: &str) -> f32 {
+        self.animation_fps
+            .get(animation)
+            .copied()
+            .unwrap_or(self.fps)
+    }
+
+    /// How long each frame of an animation is shown for, in whole milliseconds.
+    pub fn frame_duration_ms(&self, animation: &str) -> u32 {
+        (1000.0 / self.fps(animation)).round().max(1.0) as u32
+    }
+
+    /// Whether an animation starts over after its last frame.
+    pub fn looping(&self, animation: &str) -> bool {
+        !self.no_loop.contains(animation)
+    }
+
+    /// Returns an error if an animation is given a speed or told not to loop but isn't in the sheet,
+    /// which is usually a typo.
+    pub fn check_animations(&self, sheet: &SpriteSheet) -> Result<(), String> {
+        for animation in self.animation_fps.keys().chain(self.no_loop.iter()) {
+            if !sheet.sprites.contains_key(animation) {
+                return Err(format!(
+                    "There's no animation called '{}' in the sprite sheet",
+                    animation
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A frame and the name it's exported under.
+pub struct NamedFrame<'a> {
+    pub animation: &'a str,
+    pub index: usize,
+    /// The animation and the frame index, zero padded so frames sort in order
+    pub name: String,
+    pub frame: &'a Frame,
+}
+
+/// Every frame of the sheet, sorted by animation and then index.
+/// Returns an error if any frame was diced, as these formats describe each frame with a single rectangle.
+pub fn named_frames<'a>(
+    sheet: &'a SpriteSheet,
+    format: &str,
+) -> Result<Vec<NamedFrame<'a>>, String> {
+    let mut frames = vec![];
+    for (animation, animation_frames) in sheet.sprites.iter() {
+        for (index, frame) in animation_frames.iter().enumerate() {
+            if !frame.cells.is_empty() {
+                return Err(format!(
+                    "{} can't describe diced frames, so it can't be used with --dice",
+                    format
+                ));
+            }
+
+            frames.push(NamedFrame {
+                animation,
+                index,
+                name: format!("{}_{:04}", animation, index),
+                frame,
+            });
+        }
+    }
+    Ok(frames)
+}
+
+/// Returns an error if any frame was rotated, for formats with no way to turn a frame back.
+pub fn reject_rotated(frames: &[NamedFrame], format: &str) -> Result<(), String> {
+    if frames.iter().any(|f| f.frame.rotated) {
+        return Err(format!(
+            "{} can't describe rotated frames, so it can't be used with --allow-rotation",
+            format
+        ));
+    }
+    Ok(())
+}
+
+/// The page image file name without its extension, used to name files written for each page.
+pub fn page_stem(page: &Page) -> &str {
+    page.image
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(&page.image)
+}
+
+/// The 16 bit pixel format of the file a page's `image` points at, or `None` if it's an RGBA8888 PNG.
+/// Returns an error if it's a compressed texture, as these formats only describe uncompressed pixels.
+pub fn image_pixel_format(page: &Page, format: &str) -> Result<Option<PixelFormat>, String> {
+    if page.texture.as_ref().is_some_and(|t| t.file == page.image) {
+        return Err(format!(
+            "{} can't describe compressed textures, so it can't be used with --texture-format and --no-png",
+            format
+        ));
+    }
+    match &page.pixels {
+        Some(pixels) if pixels.file == page.image => {
+            PixelFormat::from_str(&pixels.format, false).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns an error if a page's `image` is the raw 16 bit pixels, which engines can't load as an image.
+pub fn reject_raw_pixels(page: &Page, format: &str) -> Result<(), String> {
+    match &page.pixels {
+        Some(pixels) if pixels.file == page.image => Err(format!(
+            "{} can't load raw {} pixels, so it can't be used with --pixel-format and --no-png",
+            format, pixels.format
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns an error unless a page's `image` is a PNG, for formats that can't load anything else.
+pub fn require_png(page: &Page, format: &str) -> Result<(), String> {
+    image_pixel_format(page, format)?;
+    reject_raw_pixels(page, format)
+}
+
+/// Whether any of the original frame was cropped away.
+pub fn is_trimmed(frame: &Frame) -> bool {
+    frame.width != frame.original_width || frame.height != frame.original_height
+}
+
+/// Escape text for use in an XML attribute or element.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote a string with backslash escapes, the way Godot resource files and RON do.
+pub fn quote_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::spritesheet::{Cell, RawPixels, Texture};
+
+    /// A frame cropped to `size` out of an `original` sized frame, with `offset` cropped off its top left.
+    pub fn frame(
+        page: usize,
+        position: [u32; 2],
+        size: [u32; 2],
+        original: [u32; 2],
+        offset: [u32; 2],
+    ) -> Frame {
+        Frame {
+            top_left_offset_x: offset[0],
+            top_left_offset_y: offset[1],
+            center_offset_x: 0,
+            center_offset_y: 0,
+            x: position[0],
+            y: position[1],
+            page,
+            rotated: false,
+            width: size[0],
+            height: size[1],
+            original_width: original[0],
+            original_height: original[1],
+            mesh: None,
+            cells: vec![],
+        }
+    }
+
+    pub fn sheet(pages: &[(&str, u32, u32)], sprites: Vec<(&str, Vec<Frame>)>) -> SpriteSheet {
+        let pages = pages
+            .iter()
+            .map(|&(image, width, height)| Page {
+                image: image.to_string(),
+                width,
+                height,
+                packing_heuristic: "max-rects".to_string(),
+                texture: None,
+                pixels: None,
+                mipmaps: vec![],
+                layers: BTreeMap::new(),
+            })
+            .collect::<Vec<Page>>();
+        SpriteSheet {
+            width: pages.iter().map(|p| p.width).max().unwrap_or(0),
+            height: pages.iter().map(|p| p.height).max().unwrap_or(0),
+            name: "Hero".to_string(),
+            pages,
+            grid: None,
+            palette: vec![],
+            premultiplied: false,
+            sprites: sprites
+                .into_iter()
+                .map(|(animation, frames)| (animation.to_string(), frames))
+                .collect(),
+        }
+    }
+
+    /// One page with a trimmed frame, an untrimmed frame and a frame trimmed on every side.
+    pub fn trimmed_sheet() -> SpriteSheet {
+        sheet(
+            &[("Hero.png", 64, 32)],
+            vec![
+                (
+                    "idle",
+                    vec![
+                        frame(0, [0, 0], [10, 12], [16, 16], [2, 1]),
+                        frame(0, [10, 0], [16, 16], [16, 16], [0, 0]),
+                    ],
+                ),
+                ("run", vec![frame(0, [26, 0], [8, 6], [12, 10], [3, 4])]),
+            ],
+        )
+    }
+
+    /// A 10x4 frame turned so it takes up 4x10 pixels of the page.
+    pub fn rotated_sheet() -> SpriteSheet {
+        sheet(
+            &[("Hero.png", 32, 32)],
+            vec![(
+                "spin",
+                vec![Frame {
+                    rotated: true,
+                    ..frame(0, [2, 3], [10, 4], [12, 8], [2, 1])
+                }],
+            )],
+        )
+    }
+
+    /// A walk that continues onto a second page, after an idle frame.
+    pub fn two_page_sheet() -> SpriteSheet {
+        sheet(
+            &[("Hero_0.png", 16, 8), ("Hero_1.png", 16, 8)],
+            vec![
+                ("idle", vec![frame(1, [0, 0], [6, 6], [8, 8], [1, 1])]),
+                (
+                    "walk",
+                    vec![
+                        frame(0, [0, 0], [8, 8], [8, 8], [0, 0]),
+                        frame(0, [8, 0], [8, 8], [8, 8], [0, 0]),
+                        frame(1, [8, 0], [8, 8], [8, 8], [0, 0]),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    /// The trimmed sheet with a frame cut into cells.
+    pub fn diced_sheet() -> SpriteSheet {
+        let mut sheet = trimmed_sheet();
+        sheet.sprites.get_mut("run").unwrap()[0].cells = vec![Cell {
+            frame_x: 0,
+            frame_y: 0,
+            width: 8,
+            height: 6,
+            x: 26,
+            y: 0,
+            page: 0,
+            rotated: false,
+        }];
+        sheet
+    }
+
+    /// The trimmed sheet written as raw 16 bit pixels instead of a PNG.
+    pub fn raw_pixels_sheet(format: &str) -> SpriteSheet {
+        let mut sheet = trimmed_sheet();
+        let page = &mut sheet.pages[0];
+        page.image = format!("Hero_{}.bin", format);
+        page.pixels = Some(RawPixels {
+            file: page.image.clone(),
+            preview: None,
+            format: format.to_string(),
+            levels: 1,
+        });
+        sheet
+    }
+
+    /// The trimmed sheet written as a compressed texture instead of a PNG.
+    pub fn compressed_sheet() -> SpriteSheet {
+        let mut sheet = trimmed_sheet();
+        let page = &mut sheet.pages[0];
+        page.image = "Hero.ktx2".to_string();
+        page.texture = Some(Texture {
+            file: page.image.clone(),
+            format: "bc3".to_string(),
+            levels: 1,
+        });
+        sheet
+    }
+
+    pub fn playback() -> Playback {
+        Playback {
+            fps: 10.0,
+            animation_fps: BTreeMap::new(),
+            no_loop: BTreeSet::new(),
+        }
+    }
+
+    /// Export a sheet that should be written as a single file, returning its name and contents.
+    pub fn export_one(exporter: &dyn AtlasExporter, sheet: &SpriteSheet) -> (String, String) {
+        let mut files = exporter.export(sheet).unwrap();
+        assert_eq!(files.len(), 1);
+        let file = files.remove(0);
+        (file.name, file.contents)
+    }
+
+    /// Export a sheet that should be refused, returning the error.
+    pub fn export_error(exporter: &dyn AtlasExporter, sheet: &SpriteSheet) -> String {
+        match exporter.export(sheet) {
+            Ok(_) => panic!("the sheet was exported"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn page_images_are_checked_before_they_are_described() {
+        let page = &trimmed_sheet().pages[0];
+        assert_eq!(image_pixel_format(page, "Test"), Ok(None));
+        assert_eq!(require_png(page, "Test"), Ok(()));
+
+        let page = &raw_pixels_sheet("rgba4444").pages[0];
+        assert_eq!(
+            image_pixel_format(page, "Test"),
+            Ok(Some(PixelFormat::Rgba4444))
+        );
+        assert!(reject_raw_pixels(page, "Test").unwrap_err().contains("rgba4444"));
+        assert!(require_png(page, "Test").is_err());
+
+        // Raw pixels written next to a PNG don't change the page image
+        let mut sheet = trimmed_sheet();
+        sheet.pages[0].pixels = raw_pixels_sheet("rgb565").pages[0].pixels.clone();
+        assert_eq!(image_pixel_format(&sheet.pages[0], "Test"), Ok(None));
+
+        let page = &compressed_sheet().pages[0];
+        assert!(image_pixel_format(page, "Test").is_err());
+        assert_eq!(reject_raw_pixels(page, "Test"), Ok(()));
+        assert!(require_png(page, "Test").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use super::{
+    escape_xml, is_trimmed, named_frames, page_stem, require_png, AtlasExporter, ExportedFile,
+};
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes Starling and Sparrow texture atlas XML.
+pub struct SparrowExporter;
+
+impl AtlasExporter for SparrowExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "Sparrow XML")?;
+
+        let mut files = vec![];
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            require_png(page, "Sparrow XML")?;
+
+            let mut out = String::new();
+            writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+            writeln!(
+                out,
+                r#"<TextureAtlas imagePath="{}">"#,
+                escape_xml(&page.image)
+            )
+            .unwrap();
+
+            for f in frames.iter().filter(|f| f.frame.page == page_index) {
+                let frame = f.frame;
+                // The region is the area taken up in the page, so it's turned along with the frame
+                let (width, height) = if frame.rotated {
+                    (frame.height, frame.width)
+                } else {
+                    (frame.width, frame.height)
+                };
+
+                write!(
+                    out,
+                    r#"    <SubTexture name="{}" x="{}" y="{}" width="{}" height="{}""#,
+                    escape_xml(&f.name),
+                    frame.x,
+                    frame.y,
+                    width,
+                    height
+                )
+                .unwrap();
+                if is_trimmed(frame) {
+                    write!(
+                        out,
+                        r#" frameX="{}" frameY="{}" frameWidth="{}" frameHeight="{}""#,
+                        -(frame.top_left_offset_x as i64),
+                        -(frame.top_left_offset_y as i64),
+                        frame.original_width,
+                        frame.original_height
+                    )
+                    .unwrap();
+                }
+                if frame.rotated {
+                    write!(out, r#" rotated="true""#).unwrap();
+                }
+                writeln!(out, "/>").unwrap();
+            }
+
+            writeln!(out, "</TextureAtlas>").unwrap();
+            files.push(ExportedFile {
+                name: format!("{}.xml", page_stem(page)),
+                contents: out,
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    #[test]
+    fn trimmed_frames_say_where_they_sit_in_the_original() {
+        let (name, contents) = export_one(&SparrowExporter, &trimmed_sheet());
+        assert_eq!(name, "Hero.xml");
+        assert_eq!(
+            contents,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<TextureAtlas imagePath="Hero.png">
+    <SubTexture name="idle_0000" x="0" y="0" width="10" height="12" frameX="-2" frameY="-1" frameWidth="16" frameHeight="16"/>
+    <SubTexture name="idle_0001" x="10" y="0" width="16" height="16"/>
+    <SubTexture name="run_0000" x="26" y="0" width="8" height="6" frameX="-3" frameY="-4" frameWidth="12" frameHeight="10"/>
+</TextureAtlas>
+"#
+        );
+    }
+
+    #[test]
+    fn rotated_frames_give_the_size_they_take_up_in_the_page() {
+        let (_, contents) = export_one(&SparrowExporter, &rotated_sheet());
+        assert_eq!(
+            contents,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<TextureAtlas imagePath="Hero.png">
+    <SubTexture name="spin_0000" x="2" y="3" width="4" height="10" frameX="-2" frameY="-1" frameWidth="12" frameHeight="8" rotated="true"/>
+</TextureAtlas>
+"#
+        );
+    }
+
+    #[test]
+    fn every_page_gets_its_own_file() {
+        let files = SparrowExporter.export(&two_page_sheet()).unwrap();
+        let files = files
+            .iter()
+            .map(|f| (f.name.as_str(), f.contents.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                (
+                    "Hero_0.xml",
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<TextureAtlas imagePath="Hero_0.png">
+    <SubTexture name="walk_0000" x="0" y="0" width="8" height="8"/>
+    <SubTexture name="walk_0001" x="8" y="0" width="8" height="8"/>
+</TextureAtlas>
+"#
+                ),
+                (
+                    "Hero_1.xml",
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<TextureAtlas imagePath="Hero_1.png">
+    <SubTexture name="idle_0000" x="0" y="0" width="6" height="6" frameX="-1" frameY="-1" frameWidth="8" frameHeight="8"/>
+    <SubTexture name="walk_0002" x="8" y="0" width="8" height="8"/>
+</TextureAtlas>
+"#
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn pages_that_arent_pngs_are_refused() {
+        let error = export_error(&SparrowExporter, &raw_pixels_sheet("rgb565"));
+        assert!(error.contains("--pixel-format"), "{error}");
+        let error = export_error(&SparrowExporter, &compressed_sheet());
+        assert!(error.contains("--texture-format"), "{error}");
+        let error = export_error(&SparrowExporter, &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+    }
+}
@@ -0,0 +1,277 @@
+use super::{image_pixel_format, is_trimmed, named_frames, page_stem, AtlasExporter, ExportedFile};
+use crate::pixel_format::PixelFormat;
+use crate::spritesheet::SpriteSheet;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Rect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    /// Where the frame is in the page. The size is before rotation, like the sprite sheet JSON.
+    frame: Rect,
+    rotated: bool,
+    trimmed: bool,
+    /// Where the cropped frame sits in the original frame
+    sprite_source_size: Rect,
+    source_size: Size,
+}
+
+#[derive(Serialize)]
+struct Meta {
+    app: String,
+    version: String,
+    image: String,
+    format: String,
+    size: Size,
+    scale: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Frames {
+    Hash(BTreeMap<String, Frame>),
+    Array(Vec<Frame>),
+}
+
+#[derive(Serialize)]
+struct Atlas {
+    frames: Frames,
+    meta: Meta,
+}
+
+/// Writes TexturePacker's generic JSON, with the frames either keyed by name or in a list.
+pub struct TexturePackerExporter {
+    pub array: bool,
+}
+
+impl AtlasExporter for TexturePackerExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "TexturePacker JSON")?;
+
+        let mut files = vec![];
+        for (page_index, page) in sheet.pages.iter().enumerate() {
+            let page_frames = frames
+                .iter()
+                .filter(|f| f.frame.page == page_index)
+                .map(|f| {
+                    let frame = f.frame;
+                    let exported = Frame {
+                        filename: self.array.then(|| f.name.clone()),
+                        frame: Rect {
+                            x: frame.x,
+                            y: frame.y,
+                            w: frame.width,
+                            h: frame.height,
+                        },
+                        rotated: frame.rotated,
+                        trimmed: is_trimmed(frame),
+                        sprite_source_size: Rect {
+                            x: frame.top_left_offset_x,
+                            y: frame.top_left_offset_y,
+                            w: frame.width,
+                            h: frame.height,
+                        },
+                        source_size: Size {
+                            w: frame.original_width,
+                            h: frame.original_height,
+                        },
+                    };
+                    (f.name.clone(), exported)
+                });
+
+            let atlas = Atlas {
+                frames: if self.array {
+                    Frames::Array(page_frames.map(|(_, f)| f).collect())
+                } else {
+                    Frames::Hash(page_frames.collect())
+                },
+                meta: Meta {
+                    app: env!("CARGO_PKG_NAME").to_string(),
+                    version: "1.0".to_string(),
+                    image: page.image.clone(),
+                    format: match image_pixel_format(page, "TexturePacker JSON")? {
+                        None => "RGBA8888",
+                        Some(PixelFormat::Rgba4444) => "RGBA4444",
+                        Some(PixelFormat::Rgb565) => "RGB565",
+                        Some(PixelFormat::Rgba5551) => "RGBA5551",
+                    }
+                    .to_string(),
+                    size: Size {
+                        w: page.width,
+                        h: page.height,
+                    },
+                    scale: "1".to_string(),
+                },
+            };
+
+            let suffix = if self.array { "array" } else { "hash" };
+            files.push(ExportedFile {
+                name: format!("{}_{}.json", page_stem(page), suffix),
+                contents: serde_json::to_string_pretty(&atlas).unwrap(),
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn export(array: bool, sheet: &SpriteSheet) -> Vec<(String, Value)> {
+        TexturePackerExporter { array }
+            .export(sheet)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.name, serde_json::from_str(&f.contents).unwrap()))
+            .collect()
+    }
+
+    fn meta(image: &str, format: &str, w: u32, h: u32) -> Value {
+        json!({
+            "app": "asset-builder",
+            "version": "1.0",
+            "image": image,
+            "format": format,
+            "size": { "w": w, "h": h },
+            "scale": "1"
+        })
+    }
+
+    #[test]
+    fn trimmed_frames_are_keyed_by_name() {
+        let files = export(false, &trimmed_sheet());
+        assert_eq!(
+            files,
+            vec![(
+                "Hero_hash.json".to_string(),
+                json!({
+                    "frames": {
+                        "idle_0000": {
+                            "frame": { "x": 0, "y": 0, "w": 10, "h": 12 },
+                            "rotated": false,
+                            "trimmed": true,
+                            "spriteSourceSize": { "x": 2, "y": 1, "w": 10, "h": 12 },
+                            "sourceSize": { "w": 16, "h": 16 }
+                        },
+                        "idle_0001": {
+                            "frame": { "x": 10, "y": 0, "w": 16, "h": 16 },
+                            "rotated": false,
+                            "trimmed": false,
+                            "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 16 },
+                            "sourceSize": { "w": 16, "h": 16 }
+                        },
+                        "run_0000": {
+                            "frame": { "x": 26, "y": 0, "w": 8, "h": 6 },
+                            "rotated": false,
+                            "trimmed": true,
+                            "spriteSourceSize": { "x": 3, "y": 4, "w": 8, "h": 6 },
+                            "sourceSize": { "w": 12, "h": 10 }
+                        }
+                    },
+                    "meta": meta("Hero.png", "RGBA8888", 64, 32)
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn rotated_frames_keep_their_unrotated_size() {
+        let files = export(true, &rotated_sheet());
+        assert_eq!(
+            files,
+            vec![(
+                "Hero_array.json".to_string(),
+                json!({
+                    "frames": [{
+                        "filename": "spin_0000",
+                        "frame": { "x": 2, "y": 3, "w": 10, "h": 4 },
+                        "rotated": true,
+                        "trimmed": true,
+                        "spriteSourceSize": { "x": 2, "y": 1, "w": 10, "h": 4 },
+                        "sourceSize": { "w": 12, "h": 8 }
+                    }],
+                    "meta": meta("Hero.png", "RGBA8888", 32, 32)
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn every_page_gets_its_own_file() {
+        let files = export(true, &two_page_sheet());
+        let names = files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Hero_0_array.json", "Hero_1_array.json"]);
+
+        let untrimmed = |filename: &str, x: u32| {
+            json!({
+                "filename": filename,
+                "frame": { "x": x, "y": 0, "w": 8, "h": 8 },
+                "rotated": false,
+                "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 8, "h": 8 },
+                "sourceSize": { "w": 8, "h": 8 }
+            })
+        };
+        assert_eq!(
+            files[0].1,
+            json!({
+                "frames": [untrimmed("walk_0000", 0), untrimmed("walk_0001", 8)],
+                "meta": meta("Hero_0.png", "RGBA8888", 16, 8)
+            })
+        );
+        assert_eq!(
+            files[1].1,
+            json!({
+                "frames": [{
+                    "filename": "idle_0000",
+                    "frame": { "x": 0, "y": 0, "w": 6, "h": 6 },
+                    "rotated": false,
+                    "trimmed": true,
+                    "spriteSourceSize": { "x": 1, "y": 1, "w": 6, "h": 6 },
+                    "sourceSize": { "w": 8, "h": 8 }
+                }, untrimmed("walk_0002", 8)],
+                "meta": meta("Hero_1.png", "RGBA8888", 16, 8)
+            })
+        );
+    }
+
+    #[test]
+    fn the_format_is_the_page_image_format() {
+        for (format, expected) in [
+            ("rgba4444", "RGBA4444"),
+            ("rgb565", "RGB565"),
+            ("rgba5551", "RGBA5551"),
+        ] {
+            let files = export(false, &raw_pixels_sheet(format));
+            let image = format!("Hero_{}.bin", format);
+            assert_eq!(files[0].1["meta"], meta(&image, expected, 64, 32));
+        }
+
+        let exporter = TexturePackerExporter { array: false };
+        assert!(export_error(&exporter, &compressed_sheet()).contains("--texture-format"));
+        assert!(export_error(&exporter, &diced_sheet()).contains("--dice"));
+    }
+}
@@ -0,0 +1,220 @@
+use super::{
+    escape_xml, named_frames, reject_rotated, require_png, AtlasExporter, ExportedFile, Playback,
+};
+use crate::spritesheet::SpriteSheet;
+use std::fmt::Write;
+
+/// Writes a Tiled image collection tileset, with a tile for every frame cut out of its page.
+pub struct TiledExporter {
+    pub playback: Playback,
+}
+
+impl AtlasExporter for TiledExporter {
+    fn export(&self, sheet: &SpriteSheet) -> Result<Vec<ExportedFile>, String> {
+        let frames = named_frames(sheet, "Tiled tilesets")?;
+
+        reject_rotated(&frames, "Tiled tilesets")?;
+        for page in sheet.pages.iter() {
+            require_png(page, "Tiled tilesets")?;
+        }
+
+        // Image collection tilesets are as big as their biggest tile
+        let tile_width = frames.iter().map(|f| f.frame.width).max().unwrap_or(0);
+        let tile_height = frames.iter().map(|f| f.frame.height).max().unwrap_or(0);
+
+        let mut out = String::new();
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(
+            out,
+            r#"<tileset version="1.10" name="{}" tilewidth="{}" tileheight="{}" tilecount="{}" columns="0">"#,
+            escape_xml(&sheet.name),
+            tile_width,
+            tile_height,
+            frames.len()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#" <grid orientation="orthogonal" width="1" height="1"/>"#
+        )
+        .unwrap();
+
+        for (id, f) in frames.iter().enumerate() {
+            let frame = f.frame;
+            let page = &sheet.pages[frame.page];
+            // Tiles can use part of an image since Tiled 1.9, so every tile shares its page
+            writeln!(
+                out,
+                r#" <tile id="{}" x="{}" y="{}" width="{}" height="{}">"#,
+                id, frame.x, frame.y, frame.width, frame.height
+            )
+            .unwrap();
+            writeln!(out, "  <properties>").unwrap();
+            writeln!(
+                out,
+                r#"   <property name="animation" value="{}"/>"#,
+                escape_xml(f.animation)
+            )
+            .unwrap();
+            writeln!(
+                out,
+                r#"   <property name="frame" type="int" value="{}"/>"#,
+                f.index
+            )
+            .unwrap();
+            // Tiled always loops animations, so games that play some once need to be told which
+            if f.index == 0 {
+                writeln!(
+                    out,
+                    r#"   <property name="loop" type="bool" value="{}"/>"#,
+                    self.playback.looping(f.animation)
+                )
+                .unwrap();
+            }
+            writeln!(out, "  </properties>").unwrap();
+            writeln!(
+                out,
+                r#"  <image source="{}" width="{}" height="{}"/>"#,
+                escape_xml(&page.image),
+                page.width,
+                page.height
+            )
+            .unwrap();
+
+            // The first frame's tile plays the whole animation
+            let tiles = frames
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.animation == f.animation)
+                .map(|(tile, _)| tile)
+                .collect::<Vec<_>>();
+            if f.index == 0 && tiles.len() > 1 {
+                let duration = self.playback.frame_duration_ms(f.animation);
+                writeln!(out, "  <animation>").unwrap();
+                for tile in tiles {
+                    writeln!(
+                        out,
+                        r#"   <frame tileid="{}" duration="{}"/>"#,
+                        tile, duration
+                    )
+                    .unwrap();
+                }
+                writeln!(out, "  </animation>").unwrap();
+            }
+            writeln!(out, " </tile>").unwrap();
+        }
+
+        writeln!(out, "</tileset>").unwrap();
+
+        Ok(vec![ExportedFile {
+            name: format!("{}.tsx", sheet.name),
+            contents: out,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    fn exporter() -> TiledExporter {
+        TiledExporter {
+            playback: playback(),
+        }
+    }
+
+    #[test]
+    fn the_first_tile_plays_every_frame_of_its_animation() {
+        let mut exporter = exporter();
+        exporter
+            .playback
+            .animation_fps
+            .insert("walk".to_string(), 8.0);
+        let (name, contents) = export_one(&exporter, &two_page_sheet());
+        assert_eq!(name, "Hero.tsx");
+        assert!(contents.contains(
+            r#" <tile id="1" x="0" y="0" width="8" height="8">
+  <properties>
+   <property name="animation" value="walk"/>
+   <property name="frame" type="int" value="0"/>
+   <property name="loop" type="bool" value="true"/>
+  </properties>
+  <image source="Hero_0.png" width="16" height="8"/>
+  <animation>
+   <frame tileid="1" duration="125"/>
+   <frame tileid="2" duration="125"/>
+   <frame tileid="3" duration="125"/>
+  </animation>
+ </tile>
+"#
+        ));
+        // The last frame is cut out of the second page
+        assert!(contents.contains(
+            r#" <tile id="3" x="8" y="0" width="8" height="8">
+  <properties>
+   <property name="animation" value="walk"/>
+   <property name="frame" type="int" value="2"/>
+  </properties>
+  <image source="Hero_1.png" width="16" height="8"/>
+ </tile>
+"#
+        ));
+        // Single frame animations aren't animated
+        assert_eq!(contents.matches("<animation>").count(), 1);
+    }
+
+    #[test]
+    fn trimmed_sheets_are_a_tile_per_frame() {
+        let mut exporter = exporter();
+        exporter.playback.no_loop.insert("run".to_string());
+        let (_, contents) = export_one(&exporter, &trimmed_sheet());
+        assert_eq!(
+            contents,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" name="Hero" tilewidth="16" tileheight="16" tilecount="3" columns="0">
+ <grid orientation="orthogonal" width="1" height="1"/>
+ <tile id="0" x="0" y="0" width="10" height="12">
+  <properties>
+   <property name="animation" value="idle"/>
+   <property name="frame" type="int" value="0"/>
+   <property name="loop" type="bool" value="true"/>
+  </properties>
+  <image source="Hero.png" width="64" height="32"/>
+  <animation>
+   <frame tileid="0" duration="100"/>
+   <frame tileid="1" duration="100"/>
+  </animation>
+ </tile>
+ <tile id="1" x="10" y="0" width="16" height="16">
+  <properties>
+   <property name="animation" value="idle"/>
+   <property name="frame" type="int" value="1"/>
+  </properties>
+  <image source="Hero.png" width="64" height="32"/>
+ </tile>
+ <tile id="2" x="26" y="0" width="8" height="6">
+  <properties>
+   <property name="animation" value="run"/>
+   <property name="frame" type="int" value="0"/>
+   <property name="loop" type="bool" value="false"/>
+  </properties>
+  <image source="Hero.png" width="64" height="32"/>
+ </tile>
+</tileset>
+"#
+        );
+    }
+
+    #[test]
+    fn pages_that_arent_pngs_are_refused() {
+        let error = export_error(&exporter(), &raw_pixels_sheet("rgb565"));
+        assert!(error.contains("--pixel-format"), "{error}");
+        let error = export_error(&exporter(), &compressed_sheet());
+        assert!(error.contains("--texture-format"), "{error}");
+        let error = export_error(&exporter(), &rotated_sheet());
+        assert!(error.contains("--allow-rotation"), "{error}");
+        let error = export_error(&exporter(), &diced_sheet());
+        assert!(error.contains("--dice"), "{error}");
+    }
+}
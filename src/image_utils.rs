@@ -0,0 +1,195 @@
+use image::{Rgba, RgbaImage};
+use std::collections::VecDeque;
+
+/// Duplicate the border pixels of the rectangle at (x, y) outward by `amount` pixels.
+/// This stops neighbouring texels from bleeding into a sprite when it's sampled with filtering.
+pub fn extrude(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, amount: u32) {
+    if width == 0 || height == 0 || amount == 0 {
+        return;
+    }
+
+    let (image_width, image_height) = image.dimensions();
+    let left = x.saturating_sub(amount);
+    let top = y.saturating_sub(amount);
+    let right = (x + width + amount).min(image_width);
+    let bottom = (y + height + amount).min(image_height);
+
+    for py in top..bottom {
+        for px in left..right {
+            let inside = px >= x && px < x + width && py >= y && py < y + height;
+            if inside {
+                continue;
+            }
+
+            // Clamp to the nearest pixel of the sprite
+            let sx = px.clamp(x, x + width - 1);
+            let sy = py.clamp(y, y + height - 1);
+            let pixel = *image.get_pixel(sx, sy);
+            image.put_pixel(px, py, pixel);
+        }
+    }
+}
+
+/// The offsets of the 8 pixels surrounding a pixel.
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// Spread the colour of visible pixels into neighbouring fully transparent pixels.
+/// The alpha is left untouched, so this only changes what filtering and mipmaps blend in at sprite edges.
+pub fn alpha_bleed(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    let mut filled = image.pixels().map(|p| p[3] != 0).collect::<Vec<_>>();
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+
+    let neighbours = |x: u32, y: u32| {
+        NEIGHBOURS.iter().filter_map(move |(dx, dy)| {
+            let nx = x as i64 + dx;
+            let ny = y as i64 + dy;
+            if nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64 {
+                Some((nx as u32, ny as u32))
+            } else {
+                None
+            }
+        })
+    };
+
+    // Start with every transparent pixel that touches a visible one
+    let mut frontier = VecDeque::new();
+    let mut queued = vec![false; filled.len()];
+    for y in 0..height {
+        for x in 0..width {
+            if !filled[index(x, y)] && neighbours(x, y).any(|(nx, ny)| filled[index(nx, ny)]) {
+                frontier.push_back((x, y));
+                queued[index(x, y)] = true;
+            }
+        }
+    }
+
+    // Grow outward one ring at a time so each ring only averages the ring before it
+    while !frontier.is_empty() {
+        let mut ring = vec![];
+        for (x, y) in frontier.drain(..) {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for (nx, ny) in neighbours(x, y) {
+                if filled[index(nx, ny)] {
+                    let p = image.get_pixel(nx, ny);
+                    sum[0] += p[0] as u32;
+                    sum[1] += p[1] as u32;
+                    sum[2] += p[2] as u32;
+                    count += 1;
+                }
+            }
+
+            // Every pixel in the frontier was queued by a filled neighbour, so count is never 0
+            let colour = Rgba([
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                0,
+            ]);
+            ring.push((x, y, colour));
+        }
+
+        for &(x, y, colour) in ring.iter() {
+            image.put_pixel(x, y, colour);
+            filled[index(x, y)] = true;
+        }
+
+        for &(x, y, _) in ring.iter() {
+            for (nx, ny) in neighbours(x, y) {
+                if !filled[index(nx, ny)] && !queued[index(nx, ny)] {
+                    queued[index(nx, ny)] = true;
+                    frontier.push_back((nx, ny));
+                }
+            }
+        }
+    }
+}
+
+/// Multiply the colour of a pixel by its alpha.
+pub fn premultiply_pixel(pixel: &mut [u8; 4]) {
+    let alpha = pixel[3] as u32;
+    for c in pixel.iter_mut().take(3) {
+        *c = ((*c as u32 * alpha + 127) / 255) as u8;
+    }
+}
+
+/// Multiply the colour of every pixel by its alpha, for renderers that blend with premultiplied alpha.
+pub fn premultiply(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        premultiply_pixel(&mut pixel.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extruded_pixels_copy_the_nearest_edge() {
+        let mut image = RgbaImage::new(8, 8);
+        let sprite = [(3, 3, 10), (4, 3, 20), (3, 4, 30), (4, 4, 40)];
+        for (x, y, red) in sprite {
+            image.put_pixel(x, y, Rgba([red, 0, 0, 255]));
+        }
+
+        extrude(&mut image, 3, 3, 2, 2, 2);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = if (1..7).contains(&x) && (1..7).contains(&y) {
+                    *image.get_pixel(x.clamp(3, 4), y.clamp(3, 4))
+                } else {
+                    Rgba([0, 0, 0, 0])
+                };
+                assert_eq!(*image.get_pixel(x, y), expected, "({}, {})", x, y);
+            }
+        }
+
+        // Borders that would go past the image are cut off
+        let mut corner = RgbaImage::from_pixel(3, 3, Rgba([0, 0, 0, 0]));
+        corner.put_pixel(0, 0, Rgba([1, 2, 3, 255]));
+        extrude(&mut corner, 0, 0, 1, 1, 2);
+        assert!(corner.pixels().all(|p| *p == Rgba([1, 2, 3, 255])));
+    }
+
+    #[test]
+    fn alpha_bleed_fills_colour_outward_without_alpha() {
+        let mut image = RgbaImage::new(6, 1);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(5, 0, Rgba([0, 0, 255, 128]));
+        // The colour of fully transparent pixels doesn't count
+        image.put_pixel(2, 0, Rgba([0, 255, 0, 0]));
+
+        alpha_bleed(&mut image);
+
+        let pixels = image.pixels().copied().collect::<Vec<_>>();
+        assert_eq!(
+            pixels,
+            [
+                Rgba([255, 0, 0, 255]),
+                Rgba([255, 0, 0, 0]),
+                Rgba([255, 0, 0, 0]),
+                Rgba([0, 0, 255, 0]),
+                Rgba([0, 0, 255, 0]),
+                Rgba([0, 0, 255, 128]),
+            ]
+        );
+
+        // Pixels between two colours get their average
+        let mut image = RgbaImage::new(3, 1);
+        image.put_pixel(0, 0, Rgba([200, 0, 0, 255]));
+        image.put_pixel(2, 0, Rgba([0, 100, 0, 255]));
+        alpha_bleed(&mut image);
+        assert_eq!(*image.get_pixel(1, 0), Rgba([100, 50, 0, 0]));
+    }
+}
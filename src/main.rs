@@ -1,8 +1,17 @@
+mod exporters;
+mod image_utils;
+mod mesh;
+mod mipmap;
+mod packing;
+mod palette;
+mod pixel_format;
 mod spritesheet;
 mod spritesheet_gen;
 mod tasks;
+mod texture;
 
 use clap::Parser;
+use spritesheet::SheetOptions;
 use spritesheet_gen::ViewType;
 use std::path::PathBuf;
 
@@ -28,6 +37,8 @@ pub enum Args {
         /// The scale of the font. Default is 12.0.
         #[clap(long, default_value = "12.0")]
         font_scale: f32,
+        #[clap(flatten)]
+        sheet_options: SheetOptions,
     },
     #[clap(about = "Generate a sprite sheet from a Blender file")]
     #[clap(
@@ -51,6 +62,8 @@ pub enum Args {
         /// A comma separated list of animations to generate. If empty, all animations will be generated.
         #[clap(long, required = false, default_value = "")]
         animations: String,
+        #[clap(flatten)]
+        sheet_options: SheetOptions,
     },
     #[clap(about = "Generate a mega sprite sheet from a directory of images")]
     #[clap(
@@ -73,6 +86,8 @@ pub enum Args {
         /// The number of rotations to generate for each sprite. Only used on 3/4 and isometric views.
         #[clap(long, required = false, default_value = "8")]
         num_rotations: u32,
+        #[clap(flatten)]
+        sheet_options: SheetOptions,
     },
     #[clap(name = "resize-imgs", about = "Resize images in a directory")]
     ResizeImgs {
@@ -93,6 +108,7 @@ fn main() -> Result<(), String> {
             text_file_extension,
             fontmap_directory,
             font_scale,
+            sheet_options,
         } => {
             tasks::font_map::run(
                 ttf,
@@ -100,6 +116,7 @@ fn main() -> Result<(), String> {
                 text_file_extension,
                 fontmap_directory,
                 font_scale,
+                sheet_options,
             )?;
         }
         Args::Blend2Sheet {
@@ -110,6 +127,7 @@ fn main() -> Result<(), String> {
             num_rotations,
             animations,
             sprite_height,
+            sheet_options,
         } => {
             tasks::blend2sheet::run(
                 blender_file,
@@ -119,6 +137,7 @@ fn main() -> Result<(), String> {
                 view_type,
                 num_rotations,
                 animations,
+                sheet_options,
             )?;
         }
         Args::MegaSheet {
@@ -129,6 +148,7 @@ fn main() -> Result<(), String> {
             sprite_height,
             view_type,
             num_rotations,
+            sheet_options,
         } => {
             tasks::mega_sheet::run(
                 source_directory,
@@ -138,6 +158,7 @@ fn main() -> Result<(), String> {
                 sprite_height,
                 view_type,
                 num_rotations,
+                sheet_options,
             )?;
         }
         Args::ResizeImgs {
@@ -0,0 +1,347 @@
+use image::{DynamicImage, GenericImageView};
+
+type Point = [f64; 2];
+
+/// How far a point can be on the wrong side of an edge and still count as on it, to allow for rounding.
+const EPSILON: f64 = 1e-9;
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+/// Returns a convex polygon that covers every pixel with an alpha above `alpha_threshold`,
+/// using at most `max_vertices` vertices. Coordinates are in pixels from the top left of the image.
+/// Vertices never leave the image, so pixels on the edge of the polygon may only have their centre covered.
+/// If the hull can't be reduced to `max_vertices`, the image bounds are returned instead, so it should be at least 4.
+pub fn outline(image: &DynamicImage, alpha_threshold: u8, max_vertices: usize) -> Vec<[f32; 2]> {
+    let rows = visible_rows(image, alpha_threshold);
+    let hull = convex_hull(&rows);
+    if hull.is_empty() {
+        return vec![];
+    }
+
+    // The centres of the outermost pixels of each row; if they're covered, every pixel between them is too
+    let centres = rows
+        .iter()
+        .flat_map(|&(y, min, max)| [min, max].map(|x| [x as f64 + 0.5, y as f64 + 0.5]))
+        .collect::<Vec<_>>();
+
+    let width = image.width() as f64;
+    let height = image.height() as f64;
+    let polygon = simplify(hull, max_vertices, width, height, &centres)
+        .unwrap_or_else(|| vec![[0.0, 0.0], [width, 0.0], [width, height], [0.0, height]]);
+
+    polygon.iter().map(|p| [p[0] as f32, p[1] as f32]).collect()
+}
+
+/// Split a convex polygon into a fan of triangles.
+pub fn triangulate(vertex_count: usize) -> Vec<[u32; 3]> {
+    (1..vertex_count.saturating_sub(1))
+        .map(|i| [0, i as u32, i as u32 + 1])
+        .collect()
+}
+
+/// The leftmost and rightmost visible pixel of every row that has one, as `(y, min_x, max_x)`.
+fn visible_rows(image: &DynamicImage, alpha_threshold: u8) -> Vec<(u32, u32, u32)> {
+    (0..image.height())
+        .filter_map(|y| {
+            let visible =
+                (0..image.width()).filter(|&x| image.get_pixel(x, y)[3] > alpha_threshold);
+            let (min, max) = visible.fold((None, None), |(min, _), x| (min.or(Some(x)), Some(x)));
+            Some((y, min?, max?))
+        })
+        .collect()
+}
+
+/// The convex hull of the corners of every visible pixel, using the monotone chain algorithm.
+fn convex_hull(rows: &[(u32, u32, u32)]) -> Vec<Point> {
+    // Only the leftmost and rightmost pixel of each row can be on the hull
+    let mut points = vec![];
+    for &(y, min, max) in rows.iter() {
+        for x in [min, max + 1] {
+            points.push([x as f64, y as f64]);
+            points.push([x as f64, y as f64 + 1.0]);
+        }
+    }
+
+    if points.is_empty() {
+        return points;
+    }
+
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    points.dedup();
+
+    let mut lower: Vec<Point> = vec![];
+    for &p in points.iter() {
+        while lower.len() >= 2 && cross(lower[lower.len() - 2], lower[lower.len() - 1], p) <= 0.0 {
+            lower.pop();
+        }
+        lower.push(p);
+    }
+
+    let mut upper: Vec<Point> = vec![];
+    for &p in points.iter().rev() {
+        while upper.len() >= 2 && cross(upper[upper.len() - 2], upper[upper.len() - 1], p) <= 0.0 {
+            upper.pop();
+        }
+        upper.push(p);
+    }
+
+    lower.pop();
+    upper.pop();
+    lower.extend(upper);
+    lower
+}
+
+fn same_point(a: Point, b: Point) -> bool {
+    (a[0] - b[0]).abs() < EPSILON && (a[1] - b[1]).abs() < EPSILON
+}
+
+/// Twice the signed area of a polygon; positive when the vertices go the same way as the hull.
+fn area2(polygon: &[Point]) -> f64 {
+    (0..polygon.len())
+        .map(|i| {
+            let a = polygon[i];
+            let b = polygon[(i + 1) % polygon.len()];
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum()
+}
+
+/// Move a vertex that's outside the `width` x `height` bounds onto the edge of them, between its neighbours
+/// `[before, prev, next, after]`, so the polygon stays convex and still covers every point in `centres`.
+/// The vertex slides along the edge it crossed to wherever leaves the smallest polygon.
+/// Returns `None` if there's nowhere on the edge it can go.
+fn pull_inside(
+    p: Point,
+    width: f64,
+    height: f64,
+    [before, prev, next, after]: [Point; 4],
+    centres: &[Point],
+) -> Option<Point> {
+    let clamped = [p[0].clamp(0.0, width), p[1].clamp(0.0, height)];
+    if clamped == p {
+        return Some(p);
+    }
+
+    // Every constraint is a cross product that has to stay positive, and each one is linear in the vertex position
+    let constraints = |p: Point| {
+        [
+            cross(before, prev, p),
+            cross(prev, p, next),
+            cross(p, next, after),
+        ]
+        .into_iter()
+        .chain(
+            centres
+                .iter()
+                .flat_map(move |&c| [cross(prev, p, c), cross(p, next, c)]),
+        )
+        .collect::<Vec<_>>()
+    };
+
+    // The line the vertex can slide along, and where on it the vertex was.
+    // Outside on both axes leaves only the corner.
+    let (base, dir, target, (mut low, mut high)): (Point, Point, f64, (f64, f64)) =
+        match (clamped[0] != p[0], clamped[1] != p[1]) {
+            (true, true) => (clamped, [0.0, 0.0], 0.0, (0.0, 0.0)),
+            (true, false) => ([clamped[0], 0.0], [0.0, 1.0], p[1], (0.0, height)),
+            _ => ([0.0, clamped[1]], [1.0, 0.0], p[0], (0.0, width)),
+        };
+    let at_base = constraints(base);
+    let at_one = constraints([base[0] + dir[0], base[1] + dir[1]]);
+
+    for (g0, g1) in at_base.into_iter().zip(at_one) {
+        let slope = g1 - g0;
+        let bound = (-EPSILON - g0) / slope;
+        if slope.abs() < EPSILON {
+            if g0 < -EPSILON {
+                return None;
+            }
+        } else if slope > 0.0 {
+            low = low.max(bound);
+        } else {
+            high = high.min(bound);
+        }
+    }
+
+    if low > high {
+        return None;
+    }
+
+    // The polygon's area changes linearly along the edge, so the smallest one is at an end
+    let area = |u: f64| area2(&[prev, [base[0] + u * dir[0], base[1] + u * dir[1]], next]);
+    let u = if (area(1.0) - area(0.0)).abs() < EPSILON {
+        target.clamp(low, high)
+    } else if area(1.0) > area(0.0) {
+        low
+    } else {
+        high
+    };
+    Some([base[0] + u * dir[0], base[1] + u * dir[1]])
+}
+
+/// Reduce the number of vertices of a convex polygon by repeatedly removing the edge that adds the least area
+/// when its neighbouring edges are extended to meet.
+/// Where they meet outside the `width` x `height` bounds, the new vertex is pulled back onto them. That's only kept
+/// if the polygon stays convex and still covers every point in `centres`, otherwise the next cheapest edge is tried.
+/// Returns `None` if it can't be reduced to `max_vertices`.
+fn simplify(
+    mut polygon: Vec<Point>,
+    max_vertices: usize,
+    width: f64,
+    height: f64,
+    centres: &[Point],
+) -> Option<Vec<Point>> {
+    while polygon.len() > max_vertices {
+        let n = polygon.len();
+        let mut best: Option<(usize, Point, f64)> = None;
+
+        for i in 0..n {
+            let before = polygon[(i + n - 2) % n];
+            let prev = polygon[(i + n - 1) % n];
+            let a = polygon[i];
+            let b = polygon[(i + 1) % n];
+            let next = polygon[(i + 2) % n];
+            let after = polygon[(i + 3) % n];
+
+            // Extend prev -> a forward and next -> b backward until they meet
+            let d1 = [a[0] - prev[0], a[1] - prev[1]];
+            let d2 = [next[0] - b[0], next[1] - b[1]];
+            let e = [b[0] - a[0], b[1] - a[1]];
+            let denom = d1[0] * d2[1] - d1[1] * d2[0];
+            if denom.abs() < f64::EPSILON {
+                continue;
+            }
+
+            let t = (e[0] * d2[1] - e[1] * d2[0]) / denom;
+            let s = (d1[0] * e[1] - d1[1] * e[0]) / denom;
+            if t < 0.0 || s < 0.0 {
+                continue;
+            }
+
+            let meet = [a[0] + t * d1[0], a[1] + t * d1[1]];
+            let Some(p) = pull_inside(meet, width, height, [before, prev, next, after], centres)
+            else {
+                continue;
+            };
+
+            let area = (area2(&[prev, p, next]) - area2(&[prev, a, b, next])) / 2.0;
+            if best
+                .map(|(_, _, best_area)| area < best_area)
+                .unwrap_or(true)
+            {
+                best = Some((i, p, area));
+            }
+        }
+
+        let (i, p, _) = best?;
+        polygon[i] = p;
+        polygon.remove((i + 1) % n);
+
+        // A vertex pulled back onto the bounds can land on its neighbour
+        polygon.dedup_by(|a, b| same_point(*a, *b));
+        if polygon.len() > 1 && same_point(polygon[0], polygon[polygon.len() - 1]) {
+            polygon.pop();
+        }
+    }
+
+    Some(polygon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    /// Create a trimmed image with every pixel whose centre passes `inside` visible.
+    fn shape(size: u32, inside: impl Fn(f64, f64) -> bool) -> DynamicImage {
+        let mut image = RgbaImage::new(size, size);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if inside(x as f64 + 0.5, y as f64 + 0.5) {
+                *pixel = Rgba([255, 255, 255, 255]);
+            }
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+
+    fn diamond(size: u32) -> DynamicImage {
+        let c = size as f64 / 2.0;
+        shape(size, |x, y| (x - c).abs() + (y - c).abs() <= c)
+    }
+
+    fn circle(size: u32) -> DynamicImage {
+        let c = size as f64 / 2.0;
+        shape(size, |x, y| (x - c).powi(2) + (y - c).powi(2) <= c * c)
+    }
+
+    fn polygon_area(vertices: &[[f32; 2]]) -> f64 {
+        let points = vertices
+            .iter()
+            .map(|v| [v[0] as f64, v[1] as f64])
+            .collect::<Vec<_>>();
+        area2(&points).abs() / 2.0
+    }
+
+    /// Check the outline is small enough, stays in the image and covers every visible pixel.
+    /// Returns how much of the image it covers, from 0 to 1.
+    fn check_outline(image: &DynamicImage, max_vertices: usize) -> f64 {
+        let vertices = outline(image, 0, max_vertices);
+        let (width, height) = (image.width() as f32, image.height() as f32);
+
+        assert!(vertices.len() >= 3 && vertices.len() <= max_vertices);
+        for v in vertices.iter() {
+            assert!(v[0] >= 0.0 && v[1] >= 0.0 && v[0] <= width && v[1] <= height);
+        }
+        for (x, y, pixel) in image.pixels() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let centre = [x as f64 + 0.5, y as f64 + 0.5];
+            for (i, a) in vertices.iter().enumerate() {
+                let b = vertices[(i + 1) % vertices.len()];
+                let a = [a[0] as f64, a[1] as f64];
+                let b = [b[0] as f64, b[1] as f64];
+                assert!(
+                    cross(a, b, centre) >= -1e-4,
+                    "pixel {},{} is outside {:?}",
+                    x,
+                    y,
+                    vertices
+                );
+            }
+        }
+
+        polygon_area(&vertices) / (image.width() * image.height()) as f64
+    }
+
+    #[test]
+    fn diamond_outline_is_smaller_than_its_bounds() {
+        for size in [9, 16, 33] {
+            let coverage = check_outline(&diamond(size), 4);
+            assert!(coverage < 0.7, "{} diamond covers {}", size, coverage);
+        }
+    }
+
+    #[test]
+    fn circle_outline_is_smaller_than_its_bounds() {
+        for size in [16, 31, 64] {
+            let coverage = check_outline(&circle(size), 8);
+            assert!(coverage < 0.9, "{} circle covers {}", size, coverage);
+        }
+    }
+
+    #[test]
+    fn outlines_never_have_too_many_vertices() {
+        let image = circle(48);
+        for max_vertices in 4..12 {
+            check_outline(&image, max_vertices);
+        }
+    }
+
+    #[test]
+    fn fully_visible_image_is_its_bounds() {
+        let image = shape(7, |_, _| true);
+        assert_eq!(check_outline(&image, 4), 1.0);
+    }
+}
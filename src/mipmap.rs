@@ -0,0 +1,307 @@
+use image::{Rgba, RgbaImage};
+
+/// Alpha tested sprites are usually cut off at half alpha, so coverage is measured against that.
+const ALPHA_TEST_REFERENCE: u8 = 128;
+
+/// A rectangle of the sheet that's downsampled on its own, so it never picks up colour from its neighbours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// The texels the region covers at a mip level, as `(x0, y0, x1, y1)` with exclusive ends.
+    fn at_level(&self, level: u32, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale = 1 << level;
+        let x0 = (self.x / scale).min(width - 1);
+        let y0 = (self.y / scale).min(height - 1);
+        let x1 = (self.x + self.width).div_ceil(scale).clamp(x0 + 1, width);
+        let y1 = (self.y + self.height).div_ceil(scale).clamp(y0 + 1, height);
+        (x0, y0, x1, y1)
+    }
+}
+
+/// Returns the number of mip levels below an image of this size, down to 1x1.
+pub fn full_chain_length(width: u32, height: u32) -> u32 {
+    31 - width.max(height).max(1).leading_zeros()
+}
+
+fn to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(value: f32) -> u8 {
+    let v = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Average some pixels in linear space, weighting colours by alpha so transparent pixels don't darken the edges.
+/// If every pixel is transparent their colours are averaged evenly, to keep any colour spread by alpha bleeding.
+fn average(pixels: &[Rgba<u8>; 4], linear: &[f32; 256]) -> Rgba<u8> {
+    let mut colour = [0.0; 3];
+    let mut plain = [0.0; 3];
+    let mut alpha = 0.0;
+    for p in pixels.iter() {
+        let a = p[3] as f32 / 255.0;
+        for c in 0..3 {
+            colour[c] += linear[p[c] as usize] * a;
+            plain[c] += linear[p[c] as usize];
+        }
+        alpha += a;
+    }
+
+    let count = pixels.len() as f32;
+    let colour = if alpha > 0.0 {
+        colour.map(|c| c / alpha)
+    } else {
+        plain.map(|c| c / count)
+    };
+
+    Rgba([
+        to_srgb(colour[0]),
+        to_srgb(colour[1]),
+        to_srgb(colour[2]),
+        (alpha / count * 255.0).round() as u8,
+    ])
+}
+
+/// Halve an image, only averaging pixels from inside the same region.
+/// Texels outside every region are averaged across the whole image, but left fully transparent.
+fn downsample(
+    previous: &RgbaImage,
+    regions: &[Region],
+    level: u32,
+    linear: &[f32; 256],
+) -> RgbaImage {
+    let width = (previous.width() / 2).max(1);
+    let height = (previous.height() / 2).max(1);
+
+    let mut image = RgbaImage::from_fn(width, height, |x, y| {
+        let mut p = average(
+            &box_pixels(previous, x, y, (0, 0, previous.width(), previous.height())),
+            linear,
+        );
+        p[3] = 0;
+        p
+    });
+
+    for region in regions.iter() {
+        let bounds = region.at_level(level - 1, previous.width(), previous.height());
+        let (x0, y0, x1, y1) = region.at_level(level, width, height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = average(&box_pixels(previous, x, y, bounds), linear);
+                image.put_pixel(x, y, p);
+            }
+        }
+    }
+
+    image
+}
+
+/// The 2x2 pixels of the previous level under a texel, clamped to `(x0, y0, x1, y1)`.
+fn box_pixels(
+    previous: &RgbaImage,
+    x: u32,
+    y: u32,
+    (x0, y0, x1, y1): (u32, u32, u32, u32),
+) -> [Rgba<u8>; 4] {
+    [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+        let sx = (x * 2 + dx).clamp(x0, x1 - 1);
+        let sy = (y * 2 + dy).clamp(y0, y1 - 1);
+        *previous.get_pixel(sx, sy)
+    })
+}
+
+/// Returns the fraction of texels in a region with an alpha at or above the alpha test reference,
+/// after scaling the alpha.
+fn coverage(image: &RgbaImage, (x0, y0, x1, y1): (u32, u32, u32, u32), scale: f32) -> f32 {
+    let mut covered = 0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if image.get_pixel(x, y)[3] as f32 * scale >= ALPHA_TEST_REFERENCE as f32 {
+                covered += 1;
+            }
+        }
+    }
+    covered as f32 / ((x1 - x0) * (y1 - y0)) as f32
+}
+
+/// Scale the alpha of a region so the same fraction of it passes the alpha test as in the full size image.
+/// Without this, alpha tested sprites like foliage get thinner and fade out in the distance.
+fn preserve_coverage(image: &mut RgbaImage, bounds: (u32, u32, u32, u32), target: f32) {
+    let (mut low, mut high) = (0.0, 4.0);
+    for _ in 0..16 {
+        let mid = (low + high) / 2.0;
+        if coverage(image, bounds, mid) < target {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    // The high end is the smallest scale that doesn't fall short of the target, if it's reachable at all.
+    // Every scale up to where the coverage grows again keeps the same coverage, so prefer leaving alpha as it is.
+    let reached = coverage(image, bounds, high);
+    let (mut same, mut above) = (high, 4.0);
+    for _ in 0..16 {
+        let mid = (same + above) / 2.0;
+        if coverage(image, bounds, mid) > reached {
+            above = mid;
+        } else {
+            same = mid;
+        }
+    }
+    let scale = 1.0f32.clamp(high, same);
+    let (x0, y0, x1, y1) = bounds;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = image.get_pixel_mut(x, y);
+            p[3] = (p[3] as f32 * scale).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Build `levels` mip levels below `image`, largest first, downsampling each region separately.
+/// Regions only stay fully apart at a level if they're aligned to a multiple of its scale,
+/// otherwise neighbouring regions can share the texels along their edges.
+pub fn generate(image: &RgbaImage, regions: &[Region], levels: u32) -> Vec<RgbaImage> {
+    let linear: [f32; 256] = std::array::from_fn(|i| to_linear(i as u8));
+    let targets = regions
+        .iter()
+        .map(|r| coverage(image, r.at_level(0, image.width(), image.height()), 1.0))
+        .collect::<Vec<_>>();
+
+    let mut mips = vec![];
+    let mut previous = image.clone();
+    for level in 1..=levels {
+        let next = downsample(&previous, regions, level, &linear);
+
+        let mut mip = next.clone();
+        for (region, &target) in regions.iter().zip(targets.iter()) {
+            if target > 0.0 {
+                let bounds = region.at_level(level, mip.width(), mip.height());
+                preserve_coverage(&mut mip, bounds, target);
+            }
+        }
+
+        mips.push(mip);
+        previous = next;
+    }
+
+    mips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Thin blades of grass, an opaque block and a soft edged disc, side by side in 32x32 regions.
+    fn sheet() -> (RgbaImage, Vec<Region>) {
+        let image = RgbaImage::from_fn(96, 32, |x, y| match x / 32 {
+            0 if y > 4 && x % 4 == 1 => Rgba([0, 200, 0, 255]),
+            1 => Rgba([0, 0, 255, 255]),
+            2 => {
+                let d = ((x as f32 - 79.5).powi(2) + (y as f32 - 15.5).powi(2)).sqrt();
+                let a = ((14.0 - d) / 8.0).clamp(0.0, 1.0);
+                Rgba([200, 0, 0, (a * 255.0).round() as u8])
+            }
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        let regions = (0..3)
+            .map(|i| Region {
+                x: i * 32,
+                y: 0,
+                width: 32,
+                height: 32,
+            })
+            .collect();
+        (image, regions)
+    }
+
+    #[test]
+    fn mipmaps_keep_alpha_coverage() {
+        let (image, regions) = sheet();
+        let levels = full_chain_length(32, 32);
+        let mips = generate(&image, &regions, levels);
+        assert_eq!(mips.len(), levels as usize);
+
+        let linear: [f32; 256] = std::array::from_fn(|i| to_linear(i as u8));
+        let mut previous = image.clone();
+        for (i, mip) in mips.iter().enumerate() {
+            let level = i as u32 + 1;
+            assert_eq!(mip.dimensions(), (96 >> level, 32 >> level));
+            let plain = downsample(&previous, &regions, level, &linear);
+
+            for (r, region) in regions.iter().enumerate() {
+                let target = coverage(&image, region.at_level(0, 96, 32), 1.0);
+                let bounds = region.at_level(level, mip.width(), mip.height());
+                let texel = 1.0 / ((bounds.2 - bounds.0) * (bounds.3 - bounds.1)) as f32;
+                let kept = coverage(mip, bounds, 1.0);
+                assert!(
+                    kept >= target - texel / 2.0,
+                    "region {r} level {level}: coverage {kept}, expected {target}"
+                );
+                assert!(kept >= coverage(&plain, bounds, 1.0).min(target));
+            }
+
+            // Opaque sprites stay opaque, and the soft disc lands on its coverage
+            let (x0, y0, x1, y1) = regions[1].at_level(level, mip.width(), mip.height());
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    assert_eq!(mip.get_pixel(x, y)[3], 255);
+                }
+            }
+            if level <= 2 {
+                let target = coverage(&image, regions[2].at_level(0, 96, 32), 1.0);
+                let kept = coverage(
+                    mip,
+                    regions[2].at_level(level, mip.width(), mip.height()),
+                    1.0,
+                );
+                assert!(
+                    (kept - target).abs() < 0.1,
+                    "disc level {level}: coverage {kept}, expected {target}"
+                );
+            }
+            previous = plain;
+        }
+
+        // The blades would fade out without scaling their alpha
+        let plain = downsample(
+            &downsample(&image, &regions, 1, &linear),
+            &regions,
+            2,
+            &linear,
+        );
+        let bounds = regions[0].at_level(2, 24, 8);
+        assert!(coverage(&plain, bounds, 1.0) < coverage(&mips[1], bounds, 1.0));
+    }
+
+    #[test]
+    fn regions_never_bleed_into_each_other() {
+        let (image, regions) = sheet();
+        for mip in generate(&image, &regions, 3) {
+            let third = mip.width() / 3;
+            for (x, y, p) in mip.enumerate_pixels() {
+                match x / third {
+                    0 => assert_eq!(p[0] | p[2], 0, "colour leaked to {x}, {y}"),
+                    1 => assert_eq!(*p, Rgba([0, 0, 255, 255])),
+                    _ => assert_eq!(p[1] | p[2], 0, "colour leaked to {x}, {y}"),
+                }
+            }
+        }
+    }
+}
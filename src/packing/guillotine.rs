@@ -0,0 +1,119 @@
+use super::{Packed, Packer, Placement, Size};
+
+#[derive(Debug, Clone, Copy)]
+struct FreeRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Packs sprites by placing each one in the best fitting free rectangle, then
+/// cutting the leftover space into two new free rectangles with a single straight cut.
+pub struct GuillotinePacker {
+    pub allow_rotation: bool,
+}
+
+impl Packer for GuillotinePacker {
+    fn pack_into(&self, sizes: &[Size], width: u32, height: u32) -> Packed {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| u64::MAX - sizes[i].width as u64 * sizes[i].height as u64);
+
+        let mut free = vec![FreeRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }];
+        let mut placements = vec![Some(Placement::default()); sizes.len()];
+
+        for i in order {
+            let size = sizes[i];
+            if size.width == 0 || size.height == 0 {
+                continue;
+            }
+
+            let orientations: &[bool] = if self.allow_rotation {
+                &[false, true]
+            } else {
+                &[false]
+            };
+
+            // Best area fit, preferring the orientation that leaves the shortest leftover side
+            let Some((index, rotated, _)) = free
+                .iter()
+                .enumerate()
+                .flat_map(|(index, f)| {
+                    orientations.iter().filter_map(move |&rotated| {
+                        let size = if rotated { size.rotated() } else { size };
+                        if size.width > f.width || size.height > f.height {
+                            return None;
+                        }
+
+                        let area = f.width as u64 * f.height as u64;
+                        let short_side = (f.width - size.width).min(f.height - size.height);
+                        Some((index, rotated, (area, short_side)))
+                    })
+                })
+                .min_by_key(|(_, _, score)| *score)
+            else {
+                placements[i] = None;
+                continue;
+            };
+            let rect = free.remove(index);
+            let size = if rotated { size.rotated() } else { size };
+
+            // Split along the shorter leftover axis so the larger leftover stays in one piece
+            let leftover_w = rect.width - size.width;
+            let leftover_h = rect.height - size.height;
+            let (right, below) = if leftover_w < leftover_h {
+                (
+                    FreeRect {
+                        x: rect.x + size.width,
+                        y: rect.y,
+                        width: leftover_w,
+                        height: size.height,
+                    },
+                    FreeRect {
+                        x: rect.x,
+                        y: rect.y + size.height,
+                        width: rect.width,
+                        height: leftover_h,
+                    },
+                )
+            } else {
+                (
+                    FreeRect {
+                        x: rect.x + size.width,
+                        y: rect.y,
+                        width: leftover_w,
+                        height: rect.height,
+                    },
+                    FreeRect {
+                        x: rect.x,
+                        y: rect.y + size.height,
+                        width: size.width,
+                        height: leftover_h,
+                    },
+                )
+            };
+
+            for r in [right, below] {
+                if r.width > 0 && r.height > 0 {
+                    free.push(r);
+                }
+            }
+
+            placements[i] = Some(Placement {
+                x: rect.x,
+                y: rect.y,
+                rotated,
+            });
+        }
+
+        Packed {
+            heuristic: "guillotine-best-area-fit".to_string(),
+            placements,
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use super::{Packed, Packer, Placement, Size};
+
+/// The heuristic used to choose which free rectangle a sprite is placed in.
+/// https://github.com/juj/RectangleBinPack/blob/master/RectangleBinPack.pdf
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heuristic {
+    /// Place the sprite where the shortest leftover side is smallest.
+    BestShortSideFit,
+    /// Place the sprite in the smallest free rectangle it fits in.
+    BestAreaFit,
+    /// Tetris style placement; place the sprite as low and then as far left as possible.
+    BottomLeft,
+    /// Place the sprite where it touches the most edges of other sprites or the sheet.
+    ContactPoint,
+}
+
+impl Heuristic {
+    /// All heuristics, in the order they are tried.
+    pub const ALL: [Heuristic; 4] = [
+        Heuristic::BestShortSideFit,
+        Heuristic::BestAreaFit,
+        Heuristic::BottomLeft,
+        Heuristic::ContactPoint,
+    ];
+
+    /// The name of the heuristic as written to the sprite sheet JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Heuristic::BestShortSideFit => "max-rects-best-short-side-fit",
+            Heuristic::BestAreaFit => "max-rects-best-area-fit",
+            Heuristic::BottomLeft => "max-rects-bottom-left",
+            Heuristic::ContactPoint => "max-rects-contact-point",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        other.x < self.right()
+            && other.right() > self.x
+            && other.y < self.bottom()
+            && other.bottom() > self.y
+    }
+}
+
+/// Returns the length two ranges overlap by.
+fn overlap(a_start: u32, a_end: u32, b_start: u32, b_end: u32) -> u32 {
+    a_end.min(b_end).saturating_sub(a_start.max(b_start))
+}
+
+/// A fixed size sheet that sprites are packed into using the MaxRects algorithm.
+pub struct MaxRectsBin {
+    width: u32,
+    height: u32,
+    free: Vec<Rect>,
+    used: Vec<Rect>,
+}
+
+impl MaxRectsBin {
+    /// Create a new empty bin.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            free: vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+            used: vec![],
+        }
+    }
+
+    /// Insert a rectangle into the bin. Returns `None` if there is no room left for it.
+    /// If `allow_rotation` is set, the rectangle may be turned 90 degrees if that fits better.
+    pub fn insert(
+        &mut self,
+        size: Size,
+        heuristic: Heuristic,
+        allow_rotation: bool,
+    ) -> Option<Placement> {
+        let mut best: Option<(Rect, bool, (u64, u64))> = None;
+
+        let orientations: &[bool] = if allow_rotation {
+            &[false, true]
+        } else {
+            &[false]
+        };
+
+        for free in self.free.iter() {
+            for &rotated in orientations {
+                let size = if rotated { size.rotated() } else { size };
+                if size.width > free.width || size.height > free.height {
+                    continue;
+                }
+
+                let rect = Rect {
+                    x: free.x,
+                    y: free.y,
+                    width: size.width,
+                    height: size.height,
+                };
+                let score = self.score(free, &rect, heuristic);
+
+                if best.map(|(_, _, s)| score < s).unwrap_or(true) {
+                    best = Some((rect, rotated, score));
+                }
+            }
+        }
+
+        let (rect, rotated, _) = best?;
+        self.place(rect);
+
+        Some(Placement {
+            x: rect.x,
+            y: rect.y,
+            rotated,
+        })
+    }
+
+    /// Score placing a rectangle in a free rectangle. Lower scores are better.
+    fn score(&self, free: &Rect, rect: &Rect, heuristic: Heuristic) -> (u64, u64) {
+        let leftover_w = (free.width - rect.width) as u64;
+        let leftover_h = (free.height - rect.height) as u64;
+        let short_side = leftover_w.min(leftover_h);
+        let long_side = leftover_w.max(leftover_h);
+
+        match heuristic {
+            Heuristic::BestShortSideFit => (short_side, long_side),
+            Heuristic::BestAreaFit => (
+                free.width as u64 * free.height as u64 - rect.width as u64 * rect.height as u64,
+                short_side,
+            ),
+            Heuristic::BottomLeft => (rect.bottom() as u64, rect.x as u64),
+            Heuristic::ContactPoint => {
+                // More contact is better, so invert it
+                (u64::MAX - self.contact_score(rect), 0)
+            }
+        }
+    }
+
+    /// Returns the length of the edges a rectangle shares with the sheet border or other sprites.
+    fn contact_score(&self, rect: &Rect) -> u64 {
+        let mut score = 0;
+
+        if rect.x == 0 || rect.right() == self.width {
+            score += rect.height as u64;
+        }
+        if rect.y == 0 || rect.bottom() == self.height {
+            score += rect.width as u64;
+        }
+
+        for used in self.used.iter() {
+            if used.x == rect.right() || used.right() == rect.x {
+                score += overlap(used.y, used.bottom(), rect.y, rect.bottom()) as u64;
+            }
+            if used.y == rect.bottom() || used.bottom() == rect.y {
+                score += overlap(used.x, used.right(), rect.x, rect.right()) as u64;
+            }
+        }
+
+        score
+    }
+
+    /// Mark a rectangle as used, splitting any free rectangles it overlaps.
+    fn place(&mut self, rect: Rect) {
+        let mut free = Vec::with_capacity(self.free.len() * 2);
+
+        for f in self.free.iter() {
+            if f.intersects(&rect) {
+                split_free_rect(f, &rect, &mut free);
+            } else {
+                free.push(*f);
+            }
+        }
+
+        self.free = free;
+        self.prune_free_list();
+        self.used.push(rect);
+    }
+
+    /// Remove any free rectangles that are fully contained by another free rectangle.
+    fn prune_free_list(&mut self) {
+        let mut i = 0;
+        while i < self.free.len() {
+            let mut j = i + 1;
+            let mut removed_i = false;
+
+            while j < self.free.len() {
+                if self.free[j].contains(&self.free[i]) {
+                    self.free.remove(i);
+                    removed_i = true;
+                    break;
+                }
+
+                if self.free[i].contains(&self.free[j]) {
+                    self.free.remove(j);
+                } else {
+                    j += 1;
+                }
+            }
+
+            if !removed_i {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Split a free rectangle into the maximal rectangles that are left after placing `used` in it.
+fn split_free_rect(free: &Rect, used: &Rect, out: &mut Vec<Rect>) {
+    // Above
+    if used.y > free.y {
+        out.push(Rect {
+            height: used.y - free.y,
+            ..*free
+        });
+    }
+
+    // Below
+    if used.bottom() < free.bottom() {
+        out.push(Rect {
+            y: used.bottom(),
+            height: free.bottom() - used.bottom(),
+            ..*free
+        });
+    }
+
+    // Left
+    if used.x > free.x {
+        out.push(Rect {
+            width: used.x - free.x,
+            ..*free
+        });
+    }
+
+    // Right
+    if used.right() < free.right() {
+        out.push(Rect {
+            x: used.right(),
+            width: free.right() - used.right(),
+            ..*free
+        });
+    }
+}
+
+/// Pack as many rectangles as possible into a sheet of the given size using a single heuristic.
+fn pack_with_heuristic(
+    sizes: &[Size],
+    width: u32,
+    height: u32,
+    heuristic: Heuristic,
+    allow_rotation: bool,
+) -> Vec<Option<Placement>> {
+    // Insert the largest rectangles first as they're the hardest to fit
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| {
+        let s = sizes[i];
+        (
+            u32::MAX - s.width.max(s.height),
+            u64::MAX - s.width as u64 * s.height as u64,
+        )
+    });
+
+    let mut bin = MaxRectsBin::new(width, height);
+    let mut placements = vec![Some(Placement::default()); sizes.len()];
+
+    for i in order {
+        let size = sizes[i];
+        if size.width == 0 || size.height == 0 {
+            continue;
+        }
+
+        placements[i] = bin.insert(size, heuristic, allow_rotation);
+    }
+
+    placements
+}
+
+/// Packs sprites using the MaxRects algorithm, trying each heuristic until one fits everything.
+/// If none of them do, the one that packed the most area is kept.
+pub struct MaxRectsPacker {
+    pub allow_rotation: bool,
+}
+
+impl Packer for MaxRectsPacker {
+    fn pack_into(&self, sizes: &[Size], width: u32, height: u32) -> Packed {
+        let mut best: Option<(u64, Packed)> = None;
+
+        for heuristic in Heuristic::ALL {
+            let packed = Packed {
+                heuristic: heuristic.name().to_string(),
+                placements: pack_with_heuristic(
+                    sizes,
+                    width,
+                    height,
+                    heuristic,
+                    self.allow_rotation,
+                ),
+            };
+            if packed.is_complete() {
+                return packed;
+            }
+
+            let packed_area = packed
+                .placements
+                .iter()
+                .zip(sizes.iter())
+                .filter(|(p, _)| p.is_some())
+                .map(|(_, s)| s.width as u64 * s.height as u64)
+                .sum::<u64>();
+            if best.as_ref().map(|(a, _)| packed_area > *a).unwrap_or(true) {
+                best = Some((packed_area, packed));
+            }
+        }
+
+        best.map(|(_, packed)| packed).unwrap()
+    }
+}
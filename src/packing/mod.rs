@@ -0,0 +1,656 @@
+pub mod guillotine;
+pub mod max_rects;
+pub mod shelf;
+pub mod skyline;
+
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::str::FromStr;
+
+/// The default largest width or height a sprite sheet page is allowed to grow to.
+pub const MAX_SHEET_SIZE: u32 = 8192;
+
+/// The size of a rectangle that needs to be packed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    /// Returns the size turned 90 degrees.
+    pub fn rotated(&self) -> Self {
+        Self {
+            width: self.height,
+            height: self.width,
+        }
+    }
+}
+
+/// The position of a packed rectangle in the sprite sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Placement {
+    pub x: u32,
+    pub y: u32,
+    /// Whether the rectangle was turned 90 degrees clockwise to fit
+    pub rotated: bool,
+}
+
+/// Rectangles packed into a sheet of a fixed size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packed {
+    /// The heuristic that produced this packing
+    pub heuristic: String,
+    /// The placement of each rectangle, in the same order as the input sizes.
+    /// Rectangles that didn't fit are `None`.
+    pub placements: Vec<Option<Placement>>,
+}
+
+impl Packed {
+    /// Returns true if every rectangle was placed.
+    pub fn is_complete(&self) -> bool {
+        self.placements.iter().all(|p| p.is_some())
+    }
+}
+
+/// A single page of packed rectangles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedPage {
+    /// The width of the page
+    pub width: u32,
+    /// The height of the page
+    pub height: u32,
+    /// The heuristic that produced this page
+    pub heuristic: String,
+    /// The index of each rectangle on this page and where it was placed
+    pub placements: Vec<(usize, Placement)>,
+}
+
+/// An algorithm that packs rectangles into a sheet.
+pub trait Packer: Sync {
+    /// Pack as many rectangles as possible into a sheet of the given size.
+    fn pack_into(&self, sizes: &[Size], width: u32, height: u32) -> Packed;
+}
+
+/// The algorithm used to pack sprites into the sprite sheet
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PackerType {
+    /// Densest packing. Tries several MaxRects heuristics and keeps the best one.
+    MaxRects,
+    /// Fastest packing. Sorts sprites by height and fills the sheet row by row.
+    Shelf,
+    /// Fast and fairly dense. Drops each sprite onto the lowest spot of the skyline.
+    Skyline,
+    /// Fast and fairly dense. Splits the leftover space with straight cuts.
+    Guillotine,
+}
+
+impl PackerType {
+    /// Returns the packer implementation for this type.
+    /// If `allow_rotation` is set, the packer may turn sprites 90 degrees to fit them better.
+    pub fn packer(&self, allow_rotation: bool) -> Box<dyn Packer> {
+        match self {
+            PackerType::MaxRects => Box::new(max_rects::MaxRectsPacker { allow_rotation }),
+            PackerType::Shelf => Box::new(shelf::ShelfPacker { allow_rotation }),
+            PackerType::Skyline => Box::new(skyline::SkylinePacker { allow_rotation }),
+            PackerType::Guillotine => Box::new(guillotine::GuillotinePacker { allow_rotation }),
+        }
+    }
+}
+
+/// How the width and height of each sheet page are chosen.
+/// Parsed from `pot`, `pot-square`, `any` or `multiple-of-N`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sizing {
+    /// Powers of 2, which every GPU supports.
+    Pot,
+    /// Square powers of 2, for older GPUs and formats like PVRTC that need them.
+    PotSquare,
+    /// Any size. Saves memory on GPUs that support non-power of 2 textures.
+    Any,
+    /// Multiples of a number of pixels.
+    MultipleOf(u32),
+}
+
+impl FromStr for Sizing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pot" => Ok(Sizing::Pot),
+            "pot-square" => Ok(Sizing::PotSquare),
+            "any" => Ok(Sizing::Any),
+            _ => match s.strip_prefix("multiple-of-").map(|n| n.parse::<u32>()) {
+                Some(Ok(n)) if n > 0 => Ok(Sizing::MultipleOf(n)),
+                _ => Err(format!(
+                    "Unknown sizing '{}', expected pot, pot-square, any or multiple-of-N",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Sizing {
+    /// Returns an error if sheets of this kind can't be made out of whole `block` sized blocks.
+    pub fn check_block(&self, block: u32) -> Result<(), String> {
+        match self {
+            Sizing::Pot | Sizing::PotSquare if !block.is_power_of_two() => Err(format!(
+                "A block size of {} can't be used with power of 2 sheets",
+                block
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns every valid width or height up to `max_size` that's made of whole blocks of `block` pixels, smallest first.
+    pub fn dimensions(&self, block: u32, max_size: u32) -> Vec<u32> {
+        match self {
+            Sizing::Pot | Sizing::PotSquare => (0..32)
+                .map(|i| 1u32 << i)
+                .filter(|&n| n >= block && n <= max_size)
+                .collect(),
+            Sizing::Any | Sizing::MultipleOf(_) => {
+                let step = self.step(block);
+                (1..=max_size / step).map(|n| n * step).collect()
+            }
+        }
+    }
+
+    /// Returns the smallest valid sheet size that's at least `size`, in whole blocks of `block` pixels.
+    pub fn round_up(&self, size: Size, block: u32) -> Size {
+        let round = |n: u32| match self {
+            Sizing::Pot | Sizing::PotSquare => n.max(1).next_power_of_two().max(block),
+            Sizing::Any | Sizing::MultipleOf(_) => {
+                n.div_ceil(self.step(block)).max(1) * self.step(block)
+            }
+        };
+        let (width, height) = (round(size.width), round(size.height));
+
+        match self {
+            Sizing::PotSquare => Size {
+                width: width.max(height),
+                height: width.max(height),
+            },
+            _ => Size { width, height },
+        }
+    }
+
+    /// The distance between valid sizes that aren't powers of 2.
+    fn step(&self, block: u32) -> u32 {
+        match self {
+            Sizing::MultipleOf(n) => n / gcd(*n, block) * block,
+            _ => block,
+        }
+    }
+}
+
+/// Returns the greatest common divisor of two numbers.
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Returns how good a sheet size is. Lower is better; the smallest area, then the squarest sheet, then the widest.
+fn sheet_score(size: &Size) -> (u64, u32, u32) {
+    (
+        size.width as u64 * size.height as u64,
+        size.width.abs_diff(size.height),
+        u32::MAX - size.width,
+    )
+}
+
+/// The number of widths searched in parallel at a time.
+/// This is fixed rather than based on the number of threads so the result is the same on every machine.
+const WIDTHS_PER_BATCH: usize = 16;
+
+/// The number of widths spread across the whole range in the first pass, when there are more than that.
+/// Each pass after it looks at a quarter of the gap between widths, on either side of the best one.
+const WIDTHS_PER_PASS: usize = 16;
+
+/// Find the smallest sheet, out of the given widths and heights, that the packer can fit every rectangle in.
+/// For each width the smallest height is binary searched, so this assumes a taller sheet never packs worse.
+/// When there are a lot of widths, they're searched coarse to fine rather than one by one.
+/// If `pot_dimensions` are given, the best power of 2 sheet is found first and the result is never bigger than it.
+fn smallest_sheet(
+    packer: &dyn Packer,
+    sizes: &[Size],
+    dimensions: &[u32],
+    pot_dimensions: Option<&[u32]>,
+    square: bool,
+) -> Option<(Size, Packed)> {
+    let min_width = sizes.iter().map(|s| s.width).max().unwrap_or(1);
+    let min_height = sizes.iter().map(|s| s.height).max().unwrap_or(1);
+    let total_area: u64 = sizes.iter().map(|s| s.width as u64 * s.height as u64).sum();
+
+    if square {
+        return dimensions
+            .iter()
+            .filter(|&&w| w >= min_width && w >= min_height && w as u64 * w as u64 >= total_area)
+            .find_map(|&width| {
+                let packed = packer.pack_into(sizes, width, width);
+                let size = Size {
+                    width,
+                    height: width,
+                };
+                packed.is_complete().then_some((size, packed))
+            });
+    }
+
+    // Power of 2 sheets are quick to find, and rule out most of the other sizes straight away
+    let mut best = pot_dimensions
+        .and_then(|pot| smallest_sheet(packer, sizes, pot, None, false))
+        .and_then(|(size, _)| {
+            let round = |n: u32| dimensions.iter().copied().find(|&d| d >= n);
+            let (width, height) = (round(size.width)?, round(size.height)?);
+            let packed = packer.pack_into(sizes, width, height);
+            packed
+                .is_complete()
+                .then_some((Size { width, height }, packed))
+        });
+
+    // Sheets more than 4 times taller than they are wide are never worth it, nor are sheets wider than the best one
+    let narrowest = min_width.max(((total_area as f64).sqrt() / 2.0) as u32);
+    let widest = best.as_ref().map(|(s, _)| s.width).unwrap_or(u32::MAX);
+    let mut widths = dimensions
+        .iter()
+        .copied()
+        .filter(|&w| w >= min_width && w <= widest)
+        .collect::<Vec<_>>();
+    let first = widths
+        .iter()
+        .rposition(|&w| w <= narrowest)
+        .unwrap_or_default();
+    widths.drain(..first);
+
+    // Try an even spread of widths, then narrow in around the best one
+    let mut stride = widths.len().div_ceil(WIDTHS_PER_PASS).max(1);
+    loop {
+        let mut candidates = widths.iter().copied().step_by(stride).collect::<Vec<_>>();
+        if let Some(&last) = widths.last() {
+            if candidates.last() != Some(&last) {
+                candidates.push(last);
+            }
+        }
+        best = best_of_widths(
+            packer,
+            sizes,
+            &candidates,
+            dimensions,
+            (min_height, total_area),
+            best,
+        );
+
+        if stride == 1 {
+            return best;
+        }
+        let centre = best
+            .as_ref()
+            .map(|(s, _)| widths.partition_point(|&w| w < s.width))?;
+        widths =
+            widths[centre.saturating_sub(stride)..(centre + stride + 1).min(widths.len())].to_vec();
+        stride = stride.div_ceil(4);
+    }
+}
+
+/// Search the given widths, smallest first, for a sheet that beats `best`.
+/// No sheet can be smaller than the area of every rectangle or than its width times the tallest rectangle,
+/// so the search stops as soon as that's bigger than the best sheet.
+fn best_of_widths(
+    packer: &dyn Packer,
+    sizes: &[Size],
+    widths: &[u32],
+    dimensions: &[u32],
+    (min_height, total_area): (u32, u64),
+    mut best: Option<(Size, Packed)>,
+) -> Option<(Size, Packed)> {
+    for batch in widths.chunks(WIDTHS_PER_BATCH) {
+        let best_score = best.as_ref().map(|(s, _)| sheet_score(s));
+        let lower_bound = (batch[0] as u64 * min_height as u64).max(total_area);
+        if best_score.map(|b| lower_bound > b.0).unwrap_or(false) {
+            break;
+        }
+
+        let found = batch
+            .par_iter()
+            .map(|&width| {
+                // Only look at heights that could fit everything and beat the best sheet so far
+                let heights = dimensions
+                    .iter()
+                    .copied()
+                    .filter(|&height| {
+                        let size = Size { width, height };
+                        height >= min_height
+                            && width as u64 * height as u64 >= total_area
+                            && best_score.map(|b| sheet_score(&size) < b).unwrap_or(true)
+                    })
+                    .collect::<Vec<_>>();
+                smallest_height(packer, sizes, width, &heights)
+            })
+            .collect::<Vec<_>>();
+
+        for (size, packed) in found.into_iter().flatten() {
+            if best
+                .as_ref()
+                .map(|(b, _)| sheet_score(&size) < sheet_score(b))
+                .unwrap_or(true)
+            {
+                best = Some((size, packed));
+            }
+        }
+    }
+
+    best
+}
+
+/// Binary search the smallest of the given heights that the packer can fit every rectangle in at this width.
+fn smallest_height(
+    packer: &dyn Packer,
+    sizes: &[Size],
+    width: u32,
+    heights: &[u32],
+) -> Option<(Size, Packed)> {
+    // Most widths can't beat the best sheet at all, so check the tallest height first
+    let tallest = *heights.last()?;
+    let packed = packer.pack_into(sizes, width, tallest);
+    if !packed.is_complete() {
+        return None;
+    }
+
+    let mut found = (tallest, packed);
+    let (mut low, mut high) = (0, heights.len() - 1);
+    while low < high {
+        let mid = (low + high) / 2;
+        let packed = packer.pack_into(sizes, width, heights[mid]);
+        if packed.is_complete() {
+            found = (heights[mid], packed);
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    let (height, packed) = found;
+    Some((Size { width, height }, packed))
+}
+
+/// Pack all rectangles into the smallest sheet the packer can fit them in.
+/// If they can't fit in a single `max_size` sheet, they're spilled onto extra pages.
+/// Every rectangle is given a multiple of `block` pixels and placed on a multiple of `block`,
+/// and page sizes are rounded to a multiple of `block` as well.
+pub fn pack(
+    packer: &dyn Packer,
+    sizes: &[Size],
+    max_size: u32,
+    block: u32,
+    sizing: Sizing,
+) -> Result<Vec<PackedPage>, String> {
+    let block = block.max(1);
+    sizing.check_block(block)?;
+    let dimensions = sizing.dimensions(block, max_size);
+    let Some(&max_size) = dimensions.last() else {
+        return Err(format!(
+            "A {} pixel block does not fit in the maximum sheet size of {}",
+            block, max_size
+        ));
+    };
+
+    if let Some(size) = sizes
+        .iter()
+        .find(|s| s.width > max_size || s.height > max_size)
+    {
+        return Err(format!(
+            "A {}x{} sprite does not fit in the maximum sheet size of {}",
+            size.width, size.height, max_size
+        ));
+    }
+
+    // Pack in units of whole blocks, so every rectangle starts and ends on a block boundary
+    let sizes = sizes
+        .iter()
+        .map(|s| Size {
+            width: s.width.div_ceil(block),
+            height: s.height.div_ceil(block),
+        })
+        .collect::<Vec<_>>();
+    let dimensions = dimensions.iter().map(|d| d / block).collect::<Vec<_>>();
+    let max_size = max_size / block;
+
+    // Other sizes start from the best power of 2 sheet, when there is one
+    let pot_dimensions = match sizing {
+        Sizing::Any | Sizing::MultipleOf(_) if block.is_power_of_two() => Some(
+            Sizing::Pot
+                .dimensions(block, max_size * block)
+                .iter()
+                .map(|d| d / block)
+                .collect::<Vec<_>>(),
+        ),
+        _ => None,
+    };
+
+    let mut pages = vec![];
+    let mut remaining: Vec<usize> = (0..sizes.len()).collect();
+
+    while !remaining.is_empty() || pages.is_empty() {
+        let remaining_sizes: Vec<Size> = remaining.iter().map(|&i| sizes[i]).collect();
+
+        // Try to fit everything that's left in the smallest page possible
+        let page = smallest_sheet(
+            packer,
+            &remaining_sizes,
+            &dimensions,
+            pot_dimensions.as_deref(),
+            sizing == Sizing::PotSquare,
+        );
+
+        // Otherwise fill up a full size page and spill the rest onto the next one
+        let (size, packed) = match page {
+            Some(page) => page,
+            None => {
+                let size = Size {
+                    width: max_size,
+                    height: max_size,
+                };
+                (size, packer.pack_into(&remaining_sizes, max_size, max_size))
+            }
+        };
+
+        let mut placements = vec![];
+        let mut unplaced = vec![];
+        for (&i, placement) in remaining.iter().zip(packed.placements.iter()) {
+            match placement {
+                Some(placement) => placements.push((i, *placement)),
+                None => unplaced.push(i),
+            }
+        }
+
+        if placements.is_empty() && !remaining.is_empty() {
+            return Err("Unable to pack any sprites onto a new page".to_string());
+        }
+
+        pages.push(PackedPage {
+            width: size.width * block,
+            height: size.height * block,
+            heuristic: packed.heuristic,
+            placements: placements
+                .into_iter()
+                .map(|(i, p)| {
+                    (
+                        i,
+                        Placement {
+                            x: p.x * block,
+                            y: p.y * block,
+                            ..p
+                        },
+                    )
+                })
+                .collect(),
+        });
+        remaining = unplaced;
+    }
+
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    /// A small deterministic random number generator, so failures can be reproduced.
+    struct Lcg(u64);
+    impl Lcg {
+        fn next(&mut self, max: u32) -> u32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 33) % max as u64) as u32
+        }
+    }
+
+    fn random_sizes(seed: u64, count: usize, margin: u32) -> Vec<Size> {
+        let mut rng = Lcg(seed);
+        (0..count)
+            .map(|_| Size {
+                width: 1 + rng.next(40) + margin,
+                height: 1 + rng.next(40) + margin,
+            })
+            .collect()
+    }
+
+    /// Check every rectangle is placed once, inside its page, on a block boundary and without overlapping another.
+    fn assert_valid(pages: &[PackedPage], sizes: &[Size], block: u32, label: &str) {
+        let mut placed = vec![false; sizes.len()];
+
+        for (page_index, page) in pages.iter().enumerate() {
+            let rects = page
+                .placements
+                .iter()
+                .map(|(i, p)| {
+                    assert!(!placed[*i], "{}: rect {} was placed twice", label, i);
+                    placed[*i] = true;
+
+                    let size = if p.rotated {
+                        sizes[*i].rotated()
+                    } else {
+                        sizes[*i]
+                    };
+                    assert!(
+                        p.x % block == 0 && p.y % block == 0,
+                        "{}: rect {} at {},{} isn't on a {} pixel block",
+                        label,
+                        i,
+                        p.x,
+                        p.y,
+                        block
+                    );
+                    assert!(
+                        p.x + size.width <= page.width && p.y + size.height <= page.height,
+                        "{}: rect {} leaves {}x{} page {}",
+                        label,
+                        i,
+                        page.width,
+                        page.height,
+                        page_index
+                    );
+                    (p.x, p.y, p.x + size.width, p.y + size.height)
+                })
+                .collect::<Vec<_>>();
+
+            for (a, ra) in rects.iter().enumerate() {
+                for rb in rects.iter().skip(a + 1) {
+                    let overlaps = ra.0 < rb.2 && rb.0 < ra.2 && ra.1 < rb.3 && rb.1 < ra.3;
+                    assert!(
+                        !overlaps,
+                        "{}: {:?} overlaps {:?} on page {}",
+                        label, ra, rb, page_index
+                    );
+                }
+            }
+        }
+
+        assert!(
+            placed.iter().all(|&p| p),
+            "{}: not every rect was placed",
+            label
+        );
+    }
+
+    #[test]
+    fn packers_keep_every_rect_inside_the_page_without_overlaps() {
+        for packer_type in PackerType::value_variants() {
+            for allow_rotation in [false, true] {
+                let packer = packer_type.packer(allow_rotation);
+                for (seed, padding, block, max_size) in [
+                    (1, 0, 1, 4096),
+                    (2, 2, 1, 4096),
+                    (3, 1, 4, 4096),
+                    (4, 3, 8, 128),
+                ] {
+                    let sizes = random_sizes(seed, 60, padding);
+                    let pages =
+                        pack(packer.as_ref(), &sizes, max_size, block, Sizing::Pot).unwrap();
+                    let label = format!(
+                        "{:?} rotation {} padding {} block {} max size {}",
+                        packer_type, allow_rotation, padding, block, max_size
+                    );
+
+                    for page in pages.iter() {
+                        assert!(page.width <= max_size && page.height <= max_size);
+                    }
+                    // Rects are rounded up to whole blocks before packing
+                    let blocked = sizes
+                        .iter()
+                        .map(|s| Size {
+                            width: s.width.div_ceil(block) * block,
+                            height: s.height.div_ceil(block) * block,
+                        })
+                        .collect::<Vec<_>>();
+                    assert_valid(&pages, &blocked, block, &label);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_sizings_are_never_bigger_than_pot() {
+        let area = |pages: &[PackedPage]| {
+            pages
+                .iter()
+                .map(|p| p.width as u64 * p.height as u64)
+                .sum::<u64>()
+        };
+
+        for packer_type in PackerType::value_variants() {
+            // MaxRects is a lot slower without optimisations, so it gets fewer sprites
+            let count = if *packer_type == PackerType::MaxRects {
+                80
+            } else {
+                300
+            };
+            let sizes = random_sizes(7, count, 8);
+            let packer = packer_type.packer(false);
+            let pot = pack(packer.as_ref(), &sizes, MAX_SHEET_SIZE, 1, Sizing::Pot).unwrap();
+
+            for sizing in [Sizing::Any, Sizing::MultipleOf(4)] {
+                let pages = pack(packer.as_ref(), &sizes, MAX_SHEET_SIZE, 1, sizing).unwrap();
+                assert!(
+                    area(&pages) <= area(&pot),
+                    "{:?} {:?} is bigger than pot",
+                    packer_type,
+                    sizing
+                );
+                assert_valid(
+                    &pages,
+                    &sizes,
+                    1,
+                    &format!("{:?} {:?}", packer_type, sizing),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::{Packed, Packer, Placement, Size};
+
+/// Packs sprites by sorting them by height and filling the sheet row by row.
+/// Fast, but wastes space when sprite heights vary a lot.
+pub struct ShelfPacker {
+    pub allow_rotation: bool,
+}
+
+impl Packer for ShelfPacker {
+    fn pack_into(&self, sizes: &[Size], width: u32, height: u32) -> Packed {
+        // Lay tall sprites on their side so rows stay short
+        let rotated = sizes
+            .iter()
+            .map(|s| self.allow_rotation && s.height > s.width && s.height <= width)
+            .collect::<Vec<_>>();
+        let sizes = sizes
+            .iter()
+            .zip(rotated.iter())
+            .map(|(s, &r)| if r { s.rotated() } else { *s })
+            .collect::<Vec<_>>();
+
+        // Sort rectangles by height
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| u32::MAX - sizes[i].height);
+
+        let mut placements = vec![None; sizes.len()];
+        let mut x_pos = 0;
+        let mut y_pos = 0;
+        let mut largest_height_this_row = 0;
+
+        for i in order {
+            let size = sizes[i];
+            if size.width > width {
+                continue;
+            }
+
+            // Start a new row if this one is full, unless there's no room left for it.
+            // Sprites that don't fit are skipped so smaller ones can still fill in the row.
+            let (x, y) = if x_pos + size.width > width {
+                (0, y_pos + largest_height_this_row)
+            } else {
+                (x_pos, y_pos)
+            };
+
+            if y + size.height > height {
+                continue;
+            }
+
+            if y != y_pos {
+                y_pos = y;
+                largest_height_this_row = 0;
+            }
+
+            placements[i] = Some(Placement {
+                x,
+                y,
+                rotated: rotated[i],
+            });
+
+            x_pos = x + size.width;
+            largest_height_this_row = largest_height_this_row.max(size.height);
+        }
+
+        Packed {
+            heuristic: "shelf".to_string(),
+            placements,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use super::{Packed, Packer, Placement, Size};
+
+/// A horizontal segment of the skyline.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// Packs sprites by tracking the top edge of everything placed so far and
+/// dropping each sprite onto the lowest spot it fits.
+pub struct SkylinePacker {
+    pub allow_rotation: bool,
+}
+
+impl SkylinePacker {
+    /// Returns the y position a rectangle would rest at if its left edge started at segment `index`.
+    fn fit(skyline: &[Segment], index: usize, size: Size, width: u32, height: u32) -> Option<u32> {
+        let x = skyline[index].x;
+        if x + size.width > width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = size.width as i64;
+        let mut i = index;
+
+        while remaining > 0 {
+            let segment = skyline.get(i)?;
+            y = y.max(segment.y);
+            if y + size.height > height {
+                return None;
+            }
+
+            remaining -= segment.width as i64;
+            i += 1;
+        }
+
+        Some(y)
+    }
+
+    /// Raise the skyline to include a newly placed rectangle.
+    fn add(skyline: &mut Vec<Segment>, index: usize, x: u32, y: u32, size: Size) {
+        let new = Segment {
+            x,
+            y: y + size.height,
+            width: size.width,
+        };
+        skyline.insert(index, new);
+
+        // Shrink or remove the segments the new one covers
+        let right = new.x + new.width;
+        let i = index + 1;
+        while i < skyline.len() {
+            let segment = skyline[i];
+            if segment.x >= right {
+                break;
+            }
+
+            let segment_right = segment.x + segment.width;
+            if segment_right <= right {
+                skyline.remove(i);
+            } else {
+                skyline[i].x = right;
+                skyline[i].width = segment_right - right;
+                break;
+            }
+        }
+
+        // Merge neighbouring segments at the same height
+        let mut i = 0;
+        while i + 1 < skyline.len() {
+            if skyline[i].y == skyline[i + 1].y {
+                skyline[i].width += skyline[i + 1].width;
+                skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Packer for SkylinePacker {
+    fn pack_into(&self, sizes: &[Size], width: u32, height: u32) -> Packed {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| (u32::MAX - sizes[i].height, u32::MAX - sizes[i].width));
+
+        let mut skyline = vec![Segment { x: 0, y: 0, width }];
+        let mut placements = vec![Some(Placement::default()); sizes.len()];
+
+        for i in order {
+            let size = sizes[i];
+            if size.width == 0 || size.height == 0 {
+                continue;
+            }
+
+            let orientations: &[bool] = if self.allow_rotation {
+                &[false, true]
+            } else {
+                &[false]
+            };
+
+            // Bottom-left; find the position with the lowest top edge, then the leftmost
+            let mut best: Option<(usize, u32, u32, bool)> = None;
+            for index in 0..skyline.len() {
+                for &rotated in orientations {
+                    let size = if rotated { size.rotated() } else { size };
+                    if let Some(y) = Self::fit(&skyline, index, size, width, height) {
+                        let x = skyline[index].x;
+                        if best
+                            .map(|(_, bx, by, _)| (y + size.height, x) < (by, bx))
+                            .unwrap_or(true)
+                        {
+                            best = Some((index, x, y + size.height, rotated));
+                        }
+                    }
+                }
+            }
+
+            placements[i] = best.map(|(index, x, top, rotated)| {
+                let size = if rotated { size.rotated() } else { size };
+                let y = top - size.height;
+                Self::add(&mut skyline, index, x, y, size);
+                Placement { x, y, rotated }
+            });
+        }
+
+        Packed {
+            heuristic: "skyline-bottom-left".to_string(),
+            placements,
+        }
+    }
+}
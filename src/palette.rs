@@ -0,0 +1,485 @@
+use crate::pixel_format::{self, Dither};
+use clap::{Parser, ValueEnum};
+use image::{Rgba, RgbaImage};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::{collections::HashMap, fs::File, io::BufWriter, path::Path};
+
+/// The palette entry every fully transparent pixel uses. It's always the first entry.
+const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+/// The most rounds of k-means refinement run on a palette.
+const K_MEANS_ITERATIONS: usize = 16;
+
+/// How a palette is picked for a sprite sheet
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Quantizer {
+    /// Repeatedly split the group of colours that varies the most. Fast and good at keeping rare, distinct colours.
+    MedianCut,
+    /// Refine the median cut palette so every entry is the average of the colours closest to it.
+    /// Slower, but closer to the original overall.
+    KMeans,
+}
+
+/// Colours are compared with their alpha multiplied in, so the colour of barely visible pixels matters less.
+fn premultiply(colour: [u8; 4]) -> [f32; 4] {
+    let a = colour[3] as f32 / 255.0;
+    [
+        colour[0] as f32 * a,
+        colour[1] as f32 * a,
+        colour[2] as f32 * a,
+        colour[3] as f32,
+    ]
+}
+
+fn unpremultiply(colour: [f32; 4]) -> [u8; 4] {
+    if colour[3] < 0.5 {
+        return TRANSPARENT;
+    }
+    let a = colour[3] / 255.0;
+    [
+        (colour[0] / a).round().clamp(0.0, 255.0) as u8,
+        (colour[1] / a).round().clamp(0.0, 255.0) as u8,
+        (colour[2] / a).round().clamp(0.0, 255.0) as u8,
+        colour[3].round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+fn distance(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+    (0..4).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+/// Returns the index of the closest entry to a premultiplied colour.
+fn nearest(entries: &[[f32; 4]], colour: &[f32; 4]) -> usize {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (i, distance(e, colour)))
+        .fold(
+            (0, f32::MAX),
+            |best, (i, d)| if d < best.1 { (i, d) } else { best },
+        )
+        .0
+}
+
+/// A group of distinct colours and how many pixels use each of them.
+struct ColourBox {
+    colours: Vec<([f32; 4], u32)>,
+    mean: [f32; 4],
+    /// The summed squared distance of every pixel from the mean
+    error: f32,
+}
+
+impl ColourBox {
+    fn new(colours: Vec<([f32; 4], u32)>) -> Self {
+        let count = colours.iter().map(|(_, n)| *n as f32).sum::<f32>();
+        let mut mean = [0.0; 4];
+        for (colour, n) in colours.iter() {
+            for c in 0..4 {
+                mean[c] += colour[c] * *n as f32 / count;
+            }
+        }
+        let error = colours
+            .iter()
+            .map(|(colour, n)| distance(colour, &mean) * *n as f32)
+            .sum();
+
+        Self {
+            colours,
+            mean,
+            error,
+        }
+    }
+
+    /// Split the box in two along the channel that varies the most, so each half has about the same number of pixels.
+    fn split(mut self) -> (Self, Self) {
+        let channel = (0..4)
+            .map(|c| {
+                let variance = self
+                    .colours
+                    .iter()
+                    .map(|(colour, n)| (colour[c] - self.mean[c]).powi(2) * *n as f32)
+                    .sum::<f32>();
+                (c, variance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0;
+        self.colours
+            .sort_by(|a, b| a.0[channel].total_cmp(&b.0[channel]));
+
+        let count = self.colours.iter().map(|(_, n)| *n).sum::<u32>();
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, n)) in self.colours.iter().enumerate() {
+            seen += n;
+            if seen * 2 >= count {
+                at = i + 1;
+                break;
+            }
+        }
+
+        // Both halves need at least one colour
+        let at = at.clamp(1, self.colours.len() - 1);
+        let second = self.colours.split_off(at);
+        (Self::new(self.colours), Self::new(second))
+    }
+}
+
+/// Split the colours into `count` boxes, returning the mean of each.
+fn median_cut(colours: Vec<([f32; 4], u32)>, count: usize) -> Vec<[f32; 4]> {
+    let mut boxes = vec![ColourBox::new(colours)];
+    while boxes.len() < count {
+        let Some((i, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colours.len() > 1)
+            .max_by(|a, b| a.1.error.total_cmp(&b.1.error))
+        else {
+            break;
+        };
+
+        let (first, second) = boxes.swap_remove(i).split();
+        boxes.push(first);
+        boxes.push(second);
+    }
+
+    boxes.iter().map(|b| b.mean).collect()
+}
+
+/// Move every entry to the average of the colours closest to it, until nothing changes.
+fn k_means(colours: &[([f32; 4], u32)], mut entries: Vec<[f32; 4]>) -> Vec<[f32; 4]> {
+    let mut assigned = vec![usize::MAX; colours.len()];
+    for _ in 0..K_MEANS_ITERATIONS {
+        let next = colours
+            .par_iter()
+            .map(|(colour, _)| nearest(&entries, colour))
+            .collect::<Vec<_>>();
+        if next == assigned {
+            break;
+        }
+        assigned = next;
+
+        let mut sums = vec![([0.0; 4], 0.0); entries.len()];
+        for ((colour, n), &i) in colours.iter().zip(assigned.iter()) {
+            for (sum, v) in sums[i].0.iter_mut().zip(colour) {
+                *sum += v * *n as f32;
+            }
+            sums[i].1 += *n as f32;
+        }
+
+        // Entries nothing is closest to keep their colour
+        for (entry, (sum, count)) in entries.iter_mut().zip(sums) {
+            if count > 0.0 {
+                *entry = sum.map(|v| v / count);
+            }
+        }
+    }
+
+    entries
+}
+
+/// Pick a palette of at most `size` colours for a set of images. The first entry is always fully transparent.
+pub fn generate(images: &[RgbaImage], size: usize, quantizer: Quantizer) -> Vec<[u8; 4]> {
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for image in images.iter() {
+        for pixel in image.pixels().filter(|p| p[3] > 0) {
+            *histogram.entry(pixel.0).or_default() += 1;
+        }
+    }
+    if histogram.is_empty() {
+        return vec![TRANSPARENT];
+    }
+
+    // Sort so the palette doesn't depend on the order the hash map returns colours in
+    let mut colours = histogram.into_iter().collect::<Vec<_>>();
+    colours.sort_unstable();
+    let colours = colours
+        .into_iter()
+        .map(|(colour, n)| (premultiply(colour), n))
+        .collect::<Vec<_>>();
+
+    let mut entries = median_cut(colours.clone(), size - 1);
+    if quantizer == Quantizer::KMeans {
+        entries = k_means(&colours, entries);
+    }
+
+    let mut palette = vec![TRANSPARENT];
+    for entry in entries.into_iter().map(unpremultiply) {
+        if !palette.contains(&entry) {
+            palette.push(entry);
+        }
+    }
+    palette
+}
+
+/// Parse a colour written as `RRGGBB` or `RRGGBBAA` hex digits, with an optional leading `#`.
+fn parse_hex(text: &str) -> Option<[u8; 4]> {
+    let text = text.trim_start_matches('#');
+    if !matches!(text.len(), 6 | 8) || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(text.get(i * 2..i * 2 + 2).unwrap_or("ff"), 16);
+    Some([
+        channel(0).ok()?,
+        channel(1).ok()?,
+        channel(2).ok()?,
+        channel(3).ok()?,
+    ])
+}
+
+/// Parse a line of a GIMP palette, which is the red, green and blue values followed by an optional name.
+fn parse_gpl(line: &str) -> Option<[u8; 4]> {
+    let mut values = line.split_whitespace().map(|v| v.parse::<u8>());
+    Some([
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+        255,
+    ])
+}
+
+/// Load a palette from a GIMP `.gpl` file or a `.hex` file with one colour per line.
+/// A fully transparent entry is added to the start, as palette files don't usually have one.
+pub fn load(path: &Path) -> Result<Vec<[u8; 4]>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read palette {:?}: {}", path, e))?;
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let gpl = match extension.as_deref() {
+        Some("gpl") => true,
+        Some("hex") => false,
+        _ => {
+            return Err(format!(
+                "Unknown palette {:?}, expected a .gpl or .hex file",
+                path
+            ))
+        }
+    };
+
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    if gpl && lines.next() != Some("GIMP Palette") {
+        return Err(format!("{:?} is not a GIMP palette", path));
+    }
+
+    let mut palette = vec![TRANSPARENT];
+    for line in lines {
+        let colour = if gpl {
+            // Skip header fields like `Columns: 8` and comments
+            if line.starts_with('#') || line.contains(':') {
+                continue;
+            }
+            parse_gpl(line)
+        } else {
+            parse_hex(line)
+        };
+
+        let colour =
+            colour.ok_or_else(|| format!("Invalid colour '{}' in palette {:?}", line, path))?;
+        if !palette.contains(&colour) {
+            palette.push(colour);
+        }
+    }
+
+    if palette.len() > 256 {
+        return Err(format!(
+            "Palette {:?} has {} colours, but at most 255 fit in an indexed PNG",
+            path,
+            palette.len() - 1
+        ));
+    }
+
+    Ok(palette)
+}
+
+/// Map every pixel of an image to the closest palette entry, replacing the pixels with the entry's colour.
+/// Returns the index of each pixel, from left to right and then top to bottom.
+pub fn remap(image: &mut RgbaImage, palette: &[[u8; 4]], dither: Dither) -> Vec<u8> {
+    let entries = palette.iter().map(|&c| premultiply(c)).collect::<Vec<_>>();
+
+    // Fully opaque pixels only pick opaque entries if there are any, so solid sprites don't get see-through holes
+    let opaque = (0..palette.len())
+        .filter(|&i| palette[i][3] == 255)
+        .collect::<Vec<_>>();
+    let opaque_entries = opaque.iter().map(|&i| entries[i]).collect::<Vec<_>>();
+
+    // Ordered dithering nudges pixels by about the gap between neighbouring entries
+    let visible = palette.iter().filter(|c| c[3] > 0).collect::<Vec<_>>();
+    let gaps = visible
+        .iter()
+        .filter_map(|a| {
+            visible
+                .iter()
+                .filter(|b| a != *b)
+                .map(|b| {
+                    (0..3)
+                        .map(|c| (a[c] as f32 - b[c] as f32).powi(2))
+                        .sum::<f32>()
+                })
+                .min_by(|x, y| x.total_cmp(y))
+                .map(f32::sqrt)
+        })
+        .collect::<Vec<_>>();
+    let spread = gaps.iter().sum::<f32>() / gaps.len().max(1) as f32;
+
+    let (width, height) = image.dimensions();
+    let mut errors = vec![[0.0f32; 4]; (width * height) as usize];
+    let mut cache: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity((width * height) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            let original = *image.get_pixel(x, y);
+            let pixel = (y * width + x) as usize;
+
+            let mut value = original.0.map(|c| c as f32);
+            for (c, v) in value.iter_mut().enumerate() {
+                if pixel_format::dithers(c, 8, &original) {
+                    *v += match dither {
+                        Dither::None => 0.0,
+                        Dither::FloydSteinberg => errors[pixel][c],
+                        Dither::Ordered => pixel_format::ordered_offset(x, y) * spread,
+                    };
+                }
+            }
+
+            let key = value.map(|v| v.round().clamp(0.0, 255.0) as u8);
+            let index = *cache.entry(key).or_insert_with(|| {
+                if key[3] == 255 && !opaque.is_empty() {
+                    opaque[nearest(&opaque_entries, &premultiply(key))] as u8
+                } else {
+                    nearest(&entries, &premultiply(key)) as u8
+                }
+            });
+            let chosen = palette[index as usize];
+
+            if dither == Dither::FloydSteinberg {
+                let mut error = [0.0; 4];
+                for c in 0..4 {
+                    if pixel_format::dithers(c, 8, &original) {
+                        error[c] = value[c].clamp(0.0, 255.0) - chosen[c] as f32;
+                    }
+                }
+
+                for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let neighbour = &mut errors[(ny as u32 * width + nx as u32) as usize];
+                    for c in 0..4 {
+                        neighbour[c] += error[c] * weight / 16.0;
+                    }
+                }
+            }
+
+            image.put_pixel(x, y, Rgba(chosen));
+            indices.push(index);
+        }
+    }
+
+    indices
+}
+
+/// Save palette indices as an 8 bit indexed PNG, with the alpha of each entry in the transparency chunk.
+pub fn save_indexed(
+    path: &Path,
+    width: u32,
+    height: u32,
+    indices: &[u8],
+    palette: &[[u8; 4]],
+) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("Unable to write {:?}: {}", path, e);
+
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect::<Vec<_>>(),
+    );
+    encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer.write_image_data(indices).map_err(|e| error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sprite with far more colours than any palette, soft edges and a transparent border.
+    fn sprite() -> RgbaImage {
+        RgbaImage::from_fn(48, 40, |x, y| {
+            let a = match (x.min(47 - x), y.min(39 - y)) {
+                (0..=1, _) | (_, 0..=1) => 0,
+                (2, _) | (_, 2) => 128,
+                _ => 255,
+            };
+            Rgba([(x * 5) as u8, (y * 6) as u8, ((x * y) % 256) as u8, a])
+        })
+    }
+
+    #[test]
+    fn palettes_never_have_more_than_the_size() {
+        let images = [sprite()];
+        for quantizer in [Quantizer::MedianCut, Quantizer::KMeans] {
+            for size in [2, 3, 16, 255, 256] {
+                let palette = generate(&images, size, quantizer);
+                assert!(
+                    palette.len() <= size,
+                    "{quantizer:?} made {} colours out of {size}",
+                    palette.len()
+                );
+                assert_eq!(palette[0], TRANSPARENT);
+                for (i, colour) in palette.iter().enumerate() {
+                    assert!(!palette[..i].contains(colour), "{colour:?} is repeated");
+                }
+
+                // Opaque pixels only have to stay opaque when the palette has an opaque colour
+                let has_opaque = palette.iter().any(|c| c[3] == 255);
+                assert!(
+                    size < 16 || has_opaque,
+                    "{quantizer:?} has no opaque colour"
+                );
+                for dither in [Dither::None, Dither::FloydSteinberg, Dither::Ordered] {
+                    let mut image = images[0].clone();
+                    let indices = remap(&mut image, &palette, dither);
+                    assert_eq!(indices.len(), (image.width() * image.height()) as usize);
+                    for (&index, (x, y, p)) in indices.iter().zip(image.enumerate_pixels()) {
+                        assert_eq!(p.0, palette[index as usize]);
+                        match images[0].get_pixel(x, y)[3] {
+                            0 => assert_eq!(index, 0),
+                            255 if has_opaque => {
+                                assert_eq!(p[3], 255, "{quantizer:?} {dither:?} made a hole")
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_images_keep_their_colours() {
+        let colours = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]];
+        let images = [RgbaImage::from_fn(6, 2, |x, y| match y {
+            0 => Rgba(colours[(x % 3) as usize]),
+            _ => Rgba(TRANSPARENT),
+        })];
+        for quantizer in [Quantizer::MedianCut, Quantizer::KMeans] {
+            let palette = generate(&images, 16, quantizer);
+            assert_eq!(palette.len(), 4);
+            for colour in colours.iter() {
+                assert!(palette.contains(colour), "{quantizer:?} lost {colour:?}");
+            }
+        }
+    }
+}
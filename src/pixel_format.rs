@@ -0,0 +1,259 @@
+use clap::{Parser, ValueEnum};
+use image::{Rgba, RgbaImage};
+
+/// A 16 bit uncompressed pixel format
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// 4 bits for each of red, green, blue and alpha.
+    Rgba4444,
+    /// 5 bits of red, 6 of green and 5 of blue, with no alpha.
+    Rgb565,
+    /// 5 bits for each of red, green and blue, and 1 bit of alpha.
+    Rgba5551,
+}
+
+impl PixelFormat {
+    /// The name of the format as written to the sprite sheet JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelFormat::Rgba4444 => "rgba4444",
+            PixelFormat::Rgb565 => "rgb565",
+            PixelFormat::Rgba5551 => "rgba5551",
+        }
+    }
+
+    /// The number of bits stored for red, green, blue and alpha.
+    fn bits(&self) -> [u32; 4] {
+        match self {
+            PixelFormat::Rgba4444 => [4, 4, 4, 4],
+            PixelFormat::Rgb565 => [5, 6, 5, 0],
+            PixelFormat::Rgba5551 => [5, 5, 5, 1],
+        }
+    }
+}
+
+/// How the error from dropping bits is hidden
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    /// Round every pixel to the nearest colour. Gradients show bands.
+    None,
+    /// Spread the error of each pixel onto the pixels to its right and below.
+    FloydSteinberg,
+    /// Nudge each pixel by a repeating 4x4 pattern. Noisier, but stable between frames and pages.
+    Ordered,
+}
+
+/// The thresholds of a 4x4 Bayer matrix, out of 16.
+const BAYER: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+/// The ordered dithering offset of a pixel, between -0.5 and 0.5 of a quantization step.
+pub fn ordered_offset(x: u32, y: u32) -> f32 {
+    (BAYER[(y % 4) as usize][(x % 4) as usize] + 0.5) / 16.0 - 0.5
+}
+
+/// Round a channel to `bits` bits, returning the stored value and the 8 bit value it reads back as.
+/// Channels with no bits always read back as fully opaque.
+fn quantize_channel(value: f32, bits: u32) -> (u16, u8) {
+    if bits == 0 {
+        return (0, 255);
+    }
+
+    let max = (1 << bits) - 1;
+    let stored = (value.clamp(0.0, 255.0) * max as f32 / 255.0).round() as u16;
+    let expanded = (stored as u32 * 255 + max / 2) / max;
+    (stored, expanded as u8)
+}
+
+/// Whether a channel of a pixel should be dithered. Alpha is left alone where it's fully transparent or opaque,
+/// so dithering never adds a halo around sprites, and 1 bit alpha is always a plain cut off.
+pub fn dithers(channel: usize, bits: u32, original: &Rgba<u8>) -> bool {
+    if original[3] == 0 {
+        return false;
+    }
+    channel < 3 || (bits > 1 && original[3] != 255)
+}
+
+/// Reduce an image to a 16 bit format. Returns what the reduced image looks like, for previewing,
+/// and the pixels packed from left to right and then top to bottom as little endian 16 bit values.
+pub fn quantize(image: &RgbaImage, format: PixelFormat, dither: Dither) -> (RgbaImage, Vec<u8>) {
+    let bits = format.bits();
+    let (width, height) = image.dimensions();
+    let mut errors = vec![[0.0f32; 4]; (width * height) as usize];
+    let mut preview = RgbaImage::new(width, height);
+    let mut bytes = Vec::with_capacity((width * height * 2) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            let original = image.get_pixel(x, y);
+            let index = (y * width + x) as usize;
+
+            let mut packed = 0u16;
+            let mut reduced = [0; 4];
+            let mut error = [0.0; 4];
+            for c in 0..4 {
+                let mut value = original[c] as f32;
+                if dithers(c, bits[c], original) {
+                    value += match dither {
+                        Dither::None => 0.0,
+                        Dither::FloydSteinberg => errors[index][c],
+                        Dither::Ordered => {
+                            ordered_offset(x, y) * 255.0 / ((1 << bits[c]) - 1) as f32
+                        }
+                    };
+                }
+
+                let (stored, expanded) = quantize_channel(value, bits[c]);
+                packed = (packed << bits[c]) | stored;
+                reduced[c] = expanded;
+                if dithers(c, bits[c], original) {
+                    error[c] = value.clamp(0.0, 255.0) - expanded as f32;
+                }
+            }
+
+            if dither == Dither::FloydSteinberg {
+                for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let neighbour = &mut errors[(ny as u32 * width + nx as u32) as usize];
+                    for c in 0..4 {
+                        neighbour[c] += error[c] * weight / 16.0;
+                    }
+                }
+            }
+
+            preview.put_pixel(x, y, Rgba(reduced));
+            bytes.extend(packed.to_le_bytes());
+        }
+    }
+
+    (preview, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [PixelFormat; 3] = [
+        PixelFormat::Rgba4444,
+        PixelFormat::Rgb565,
+        PixelFormat::Rgba5551,
+    ];
+    const DITHERS: [Dither; 3] = [Dither::None, Dither::FloydSteinberg, Dither::Ordered];
+
+    /// A shallow gradient of every channel, which bands without dithering,
+    /// with transparent and opaque rows at the top.
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(64, 16, |x, y| {
+            let v = (96 + x / 4 + y) as u8;
+            match y {
+                0 => Rgba([v, v, v, 0]),
+                1 => Rgba([v, 255 - v, v, 255]),
+                _ => Rgba([v, 255 - v, v / 2, 255 - v]),
+            }
+        })
+    }
+
+    /// Read a packed pixel back, the way the GPU would.
+    fn unpack(packed: u16, bits: [u32; 4]) -> Rgba<u8> {
+        let mut shift = bits.iter().sum::<u32>();
+        Rgba(bits.map(|b| {
+            if b == 0 {
+                return 255;
+            }
+            shift -= b;
+            let max = (1 << b) - 1;
+            let stored = (packed as u32 >> shift) & max;
+            ((stored * 255 + max / 2) / max) as u8
+        }))
+    }
+
+    /// The average of a channel over an 8x8 block, roughly what the eye sees from a distance.
+    fn mean(image: &RgbaImage, channel: usize, bx: u32, by: u32) -> f32 {
+        let mut sum = 0.0;
+        for y in by * 8..by * 8 + 8 {
+            for x in bx * 8..bx * 8 + 8 {
+                sum += image.get_pixel(x, y)[channel] as f32;
+            }
+        }
+        sum / 64.0
+    }
+
+    #[test]
+    fn packed_pixels_read_back_as_the_preview() {
+        let image = gradient();
+        for format in FORMATS {
+            for dither in DITHERS {
+                let (preview, bytes) = quantize(&image, format, dither);
+                assert_eq!(bytes.len(), (image.width() * image.height() * 2) as usize);
+                for (i, (_, _, p)) in preview.enumerate_pixels().enumerate() {
+                    let packed = u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
+                    assert_eq!(unpack(packed, format.bits()), *p, "{format:?} {dither:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undithered_pixels_round_to_the_nearest_value() {
+        let image = gradient();
+        for format in FORMATS {
+            let (preview, _) = quantize(&image, format, Dither::None);
+            for (x, y, p) in preview.enumerate_pixels() {
+                let original = image.get_pixel(x, y);
+                for (c, &bits) in format.bits().iter().enumerate() {
+                    let expected = match bits {
+                        0 => 255,
+                        _ => quantize_channel(original[c] as f32, bits).1,
+                    };
+                    // Nothing representable is nearer than the value chosen
+                    let step = 255.0 / ((1u32 << bits.max(1)) - 1) as f32;
+                    let error = (p[c] as f32 - original[c] as f32).abs();
+                    assert_eq!(p[c], expected, "{format:?} at {x}, {y}");
+                    assert!(bits == 0 || error <= step / 2.0 + 0.5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dithering_keeps_the_average_and_the_edges() {
+        let image = gradient();
+        for format in FORMATS {
+            let bits = format.bits();
+            let (plain, _) = quantize(&image, format, Dither::None);
+            for dither in [Dither::FloydSteinberg, Dither::Ordered] {
+                let (preview, _) = quantize(&image, format, dither);
+
+                // Transparent and opaque pixels stay that way, and 1 bit alpha is never dithered
+                for (x, y, p) in preview.enumerate_pixels() {
+                    let original = image.get_pixel(x, y);
+                    if original[3] == 0 || original[3] == 255 || bits[3] <= 1 {
+                        assert_eq!(p[3], plain.get_pixel(x, y)[3], "{format:?} {dither:?}");
+                    }
+                }
+
+                // Bands are broken up, so the gradient averages closer to the original
+                let mut plain_error = 0.0;
+                let mut dithered_error = 0.0;
+                for bx in 0..image.width() / 8 {
+                    for c in 0..4 {
+                        let expected = mean(&image, c, bx, 1);
+                        plain_error += (mean(&plain, c, bx, 1) - expected).abs();
+                        dithered_error += (mean(&preview, c, bx, 1) - expected).abs();
+                    }
+                }
+                assert!(
+                    dithered_error < plain_error,
+                    "{format:?} {dither:?}: {dithered_error} against {plain_error} undithered"
+                );
+            }
+        }
+    }
+}
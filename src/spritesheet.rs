@@ -1,10 +1,19 @@
-use image::{DynamicImage, GenericImage, GenericImageView};
-use serde::{Deserialize, Serialize, Serializer};
+use crate::exporters::{AnimationFps, AtlasFormat, Playback};
+use crate::image_utils;
+use crate::mesh;
+use crate::mipmap::{self, Region};
+use crate::packing::{self, PackedPage, PackerType, Size, Sizing};
+use crate::palette::{self, Quantizer};
+use crate::pixel_format::{self, Dither, PixelFormat};
+use crate::texture::{self, Container, TextureFormat};
+use clap::{Parser, ValueEnum};
+use image::{DynamicImage, GenericImage, GenericImageView, RgbaImage};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
     path::PathBuf,
 };
-use uuid::Uuid;
 
 /// A rectangle that contains an image and its position in the sprite sheet.
 /// This is used to pack the images into the sprite sheet.
@@ -15,287 +24,1336 @@ struct Rect {
     image: DynamicImage,
     x: u32,
     y: u32,
-    id: Uuid,
+    page: usize,
+    rotated: bool,
+    /// Extra images that line up with `image`, such as normal or emissive maps, by layer name
+    layers: BTreeMap<String, DynamicImage>,
+}
+impl Rect {
+    /// Crop the image and every layer to the same area.
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            image: self.image.crop_imm(x, y, width, height),
+            layers: self
+                .layers
+                .iter()
+                .map(|(name, layer)| (name.clone(), layer.crop_imm(x, y, width, height)))
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the image and every layer match another rect pixel for pixel.
+    fn same_pixels(&self, other: &Rect) -> bool {
+        self.image == other.image && self.layers == other.layers
+    }
 }
 struct DuplicateImageRect {
     animation: String,
     frame_index: usize,
-    x: u32,
-    y: u32,
-    id: Uuid,
-    reference_rect_id: Uuid,
+    /// The index of the rect in the packed list that this frame reuses
+    reference: usize,
+    /// The mean error of a near duplicate merged by --dedup-tolerance, or `None` for an exact one
+    error: Option<f32>,
 }
 impl DuplicateImageRect {
-    fn from_rect(r: &Rect, reference_rect_id: Uuid) -> Self {
+    fn from_rect(r: &Rect, reference: usize, error: Option<f32>) -> Self {
         Self {
             animation: r.animation.clone(),
             frame_index: r.frame_index,
-            x: r.x,
-            y: r.y,
-            id: r.id,
-            reference_rect_id,
+            reference,
+            error,
+        }
+    }
+}
+
+/// A unique cell drawn at a position in a diced frame.
+struct CellRef {
+    frame_x: u32,
+    frame_y: u32,
+    /// The index of the cell in the packed list
+    cell: usize,
+}
+
+/// The inclusive bounds of a region of an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bounds {
+    min_x: u32,
+    min_y: u32,
+    max_x: u32,
+    max_y: u32,
+}
+impl Bounds {
+    /// The bounds of the whole image.
+    fn of_image(image: &DynamicImage) -> Self {
+        Self {
+            min_x: 0,
+            min_y: 0,
+            max_x: image.width().saturating_sub(1),
+            max_y: image.height().saturating_sub(1),
+        }
+    }
+
+    /// The bounds of every pixel with an alpha above `threshold`, or `None` if there aren't any.
+    fn of_visible_pixels(image: &DynamicImage, threshold: u8) -> Option<Self> {
+        let mut bounds: Option<Self> = None;
+
+        for (x, y, pixel) in image.pixels() {
+            if pixel[3] > threshold {
+                let b = bounds.get_or_insert(Self {
+                    min_x: x,
+                    min_y: y,
+                    max_x: x,
+                    max_y: y,
+                });
+                b.min_x = b.min_x.min(x);
+                b.min_y = b.min_y.min(y);
+                b.max_x = b.max_x.max(x);
+                b.max_y = b.max_y.max(y);
+            }
+        }
+
+        bounds
+    }
+
+    /// The bounds a trimmed frame was cropped to, or `None` if it's empty.
+    fn of_crop(frame: &Frame) -> Option<Self> {
+        if frame.width == 0 || frame.height == 0 {
+            return None;
         }
+
+        Some(Self {
+            min_x: frame.top_left_offset_x,
+            min_y: frame.top_left_offset_y,
+            max_x: frame.top_left_offset_x + frame.width - 1,
+            max_y: frame.top_left_offset_y + frame.height - 1,
+        })
+    }
+
+    /// Draw an image cropped to these bounds where it sits inside the larger `canvas` bounds.
+    fn place(&self, image: &DynamicImage, canvas: &Self) -> DynamicImage {
+        let mut placed = RgbaImage::new(canvas.width(), canvas.height());
+        image::imageops::replace(
+            &mut placed,
+            &image.to_rgba8(),
+            (self.min_x - canvas.min_x) as i64,
+            (self.min_y - canvas.min_y) as i64,
+        );
+        DynamicImage::ImageRgba8(placed)
     }
+
+    fn union(&self, other: &Self) -> Self {
+        Self {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    /// Shrink the bounds to fit inside an image, or `None` if they don't overlap it.
+    fn clamp_to(&self, image: &DynamicImage) -> Option<Self> {
+        let bounds = Self {
+            max_x: self.max_x.min(image.width().checked_sub(1)?),
+            max_y: self.max_y.min(image.height().checked_sub(1)?),
+            ..*self
+        };
+
+        if bounds.min_x > bounds.max_x || bounds.min_y > bounds.max_y {
+            return None;
+        }
+
+        Some(bounds)
+    }
+
+    fn width(&self) -> u32 {
+        self.max_x - self.min_x + 1
+    }
+
+    fn height(&self) -> u32 {
+        self.max_y - self.min_y + 1
+    }
+}
+
+/// How sprites are cropped before they're packed
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TrimMode {
+    /// Keep the full frame.
+    None,
+    /// Crop each frame to the pixels with an alpha above --alpha-threshold.
+    AlphaThreshold,
+    /// Crop every frame of an animation to the union of their bounds, so frames don't jitter.
+    AnimationUnion,
+}
+
+/// How sprites are arranged in the sprite sheet
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// Trim and densely pack sprites.
+    Packed,
+    /// Untrimmed, equally sized cells with one animation per row and frames from left to right.
+    Grid,
+}
+
+/// Options that control how a sprite sheet is packed and saved
+#[derive(clap::Args, Debug, Clone)]
+pub struct SheetOptions {
+    /// The algorithm used to pack sprites into the sheet
+    #[clap(long, default_value = "max-rects")]
+    pub packer: PackerType,
+    /// The largest width or height of a sheet. Sprites that don't fit are spilled onto extra pages.
+    #[clap(long, default_value_t = packing::MAX_SHEET_SIZE, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_size: u32,
+    /// Allow sprites to be turned 90 degrees clockwise when that packs them better
+    #[clap(long)]
+    pub allow_rotation: bool,
+    /// The number of empty pixels to leave between sprites
+    #[clap(long, default_value = "0")]
+    pub padding: u32,
+    /// The number of pixels to duplicate the border of each sprite outward by
+    #[clap(long, default_value = "0")]
+    pub extrude: u32,
+    /// Spread the colour of sprite edges into the surrounding transparent pixels
+    #[clap(long)]
+    pub alpha_bleed: bool,
+    /// Merge frames of the same original size whose mean per-channel difference (0-255) is at most this value
+    #[clap(long, default_value = "0.0", value_parser = parse_dedup_tolerance)]
+    pub dedup_tolerance: f32,
+    /// How sprites are cropped before they're packed
+    #[clap(long, default_value = "alpha-threshold")]
+    pub trim: TrimMode,
+    /// Pixels with an alpha at or below this value are trimmed away
+    #[clap(long, default_value = "0")]
+    pub alpha_threshold: u8,
+    /// How sprites are arranged in the sheet
+    #[clap(long, default_value = "packed")]
+    pub layout: Layout,
+    /// Export a tight convex polygon for every frame to cut down on drawing transparent pixels
+    #[clap(long)]
+    pub polygon: bool,
+    /// The most vertices a frame's polygon can have, at least 4. Frames that can't be covered by that few vertices use their bounding box.
+    #[clap(long, default_value = "8", value_parser = clap::value_parser!(u32).range(4..))]
+    pub max_vertices: u32,
+    /// Cut each frame into square cells of this many pixels and only pack the unique, non-empty cells. 0 disables dicing.
+    #[clap(long, default_value = "0")]
+    pub dice: u32,
+    /// Align every sprite to blocks of this many pixels and round the sheet size to a multiple of it,
+    /// so block compressed formats never mix two sprites in one block
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub block_align: u32,
+    /// How the width and height of each sheet page are chosen: pot, pot-square, any or multiple-of-N
+    #[clap(long, default_value = "pot")]
+    pub sizing: Sizing,
+    /// Also write each page as a GPU compressed texture in this format
+    #[clap(long)]
+    pub texture_format: Option<TextureFormat>,
+    /// The file format compressed textures are written in
+    #[clap(long, default_value = "ktx2")]
+    pub texture_container: Container,
+    /// Only write the compressed textures or 16 bit pixels, not the PNGs
+    #[clap(long)]
+    pub no_png: bool,
+    /// The number of mip levels to generate below each page, stopping at 1x1. 0 disables mipmaps.
+    /// Needs a --block-align of at least 2^N so sprites stay apart in every level.
+    #[clap(long, default_value = "0")]
+    pub mip_levels: u32,
+    /// Also write each page as raw 16 bit pixels in this format, with a PNG preview of how it looks
+    #[clap(long)]
+    pub pixel_format: Option<PixelFormat>,
+    /// How pages are dithered when they're reduced to a palette or --pixel-format
+    #[clap(long, default_value = "none")]
+    pub dither: Dither,
+    /// Reduce the sheet to at most this many colours, including a transparent one, and save indexed PNGs. 0 disables it.
+    #[clap(long, default_value = "0", value_parser = clap::value_parser!(u32).range(0..=256))]
+    pub palette_size: u32,
+    /// How the palette is picked when --palette-size is set
+    #[clap(long, default_value = "median-cut")]
+    pub quantizer: Quantizer,
+    /// Reduce the sheet to the colours of a .gpl or .hex palette file and save indexed PNGs
+    #[clap(long)]
+    pub palette_file: Option<PathBuf>,
+    /// Multiply the colour of every pixel by its alpha, for renderers that blend with premultiplied alpha
+    #[clap(long)]
+    pub premultiply: bool,
+    /// Also write the layout in a format an engine or framework can load. Can be repeated.
+    #[clap(long = "format")]
+    pub formats: Vec<AtlasFormat>,
+    /// The frames per second animations play at in formats that describe animations
+    #[clap(long, default_value = "10.0")]
+    pub fps: f32,
+    /// The frames per second of one animation, as ANIMATION=FPS. Can be repeated.
+    #[clap(long)]
+    pub animation_fps: Vec<AnimationFps>,
+    /// An animation that plays once instead of looping. Can be repeated.
+    #[clap(long)]
+    pub no_loop: Vec<String>,
 }
 
 /// A sprite sheet that contains a collection of sprites and an image
 pub struct SpriteSheetBuilder {
     sheet: SpriteSheet,
     sprites_to_add: Vec<Rect>,
+    options: SheetOptions,
 }
 impl SpriteSheetBuilder {
     /// Create a new sprite sheet builder. The width and height will be resized to be a power of 2 automatically.
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, options: SheetOptions) -> Self {
         Self {
             sheet: SpriteSheet {
                 width: 0,
                 height: 0,
                 name,
-                sprites: HashMap::new(),
+                pages: vec![],
+                grid: None,
+                palette: vec![],
+                premultiplied: false,
+                sprites: BTreeMap::new(),
             },
             sprites_to_add: vec![],
+            options,
         }
     }
 
     /// Add a sprite to the sprite sheet.
     pub fn add_sprite(&mut self, animation_name: String, img: DynamicImage) {
-        // Crop image and calculate offsets for drawing a centered image
-        let original_width = img.width();
-        let original_height = img.height();
-
-        let mut min_x = img.width() - 1;
-        let mut min_y = img.height() - 1;
-        let mut max_x = 0;
-        let mut max_y = 0;
-
-        for x in 0..img.width() {
-            for y in 0..img.height() {
-                let pixel = img.get_pixel(x, y);
-                if pixel[3] != 0 {
-                    min_x = min_x.min(x);
-                    min_y = min_y.min(y);
-                    max_x = max_x.max(x);
-                    max_y = max_y.max(y);
-                }
-            }
-        }
-
-        // crop image
-        let mut top_left_offset_x = min_x;
-        let mut top_left_offset_y = min_y;
-
-        let img_is_empty = min_x == img.width() - 1 || min_y == img.height() - 1;
-        let image = if img_is_empty {
-            // Pretty weak but we'll just return an empty image.
-            // This way we can still add the frame to the sheet
-            // and the real edge case is that space will show up
-            // the right size in font maps.
-            let w = img.width() / 2;
-            let h = img.height() / 2;
-
-            top_left_offset_x = w;
-            top_left_offset_y = h;
-            DynamicImage::new_rgba8(w, h) // empty
-        } else {
-            let mut image = img;
-            image = image.crop(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1);
-
-            image
-        };
+        self.add_layered_sprite(animation_name, img, BTreeMap::new());
+    }
 
+    /// Add a sprite with extra images that line up with it, like normal or emissive maps, by layer name.
+    /// Layers must be the same size as the sprite. They're trimmed and packed using the sprite's alpha
+    /// and each one is saved to its own page images, with the same layout as the sprite sheet.
+    pub fn add_layered_sprite(
+        &mut self,
+        animation_name: String,
+        img: DynamicImage,
+        layers: BTreeMap<String, DynamicImage>,
+    ) {
         // Make sure sheet exists
-        if self.sheet.sprites.get(&animation_name).is_none() {
-            self.sheet.sprites.insert(animation_name.clone(), vec![]);
-        }
-
-        let animation = self.sheet.sprites.get_mut(&animation_name).unwrap();
+        let animation = self
+            .sheet
+            .sprites
+            .entry(animation_name.clone())
+            .or_default();
         let idx = animation.len();
 
-        let width = image.width();
-        let height = image.height();
-
-        // Now update that animation
+        // The frame is trimmed when the sheet is saved, as some trim modes need every frame of the animation
         animation.push(Frame {
-            top_left_offset_x,
-            top_left_offset_y,
+            top_left_offset_x: 0,
+            top_left_offset_y: 0,
             x: 0,
             y: 0,
-            width,
-            height,
-            original_width,
-            original_height,
-            center_offset_x: (original_width / 2) as i32 - (min_x as i32),
-            center_offset_y: (original_height / 2) as i32 - (min_y as i32),
+            page: 0,
+            rotated: false,
+            width: img.width(),
+            height: img.height(),
+            original_width: img.width(),
+            original_height: img.height(),
+            center_offset_x: 0,
+            center_offset_y: 0,
+            mesh: None,
+            cells: vec![],
         });
 
-        // Add sprite to list of sprites to add
+        // Add sprite to list of sprites to add.
+        // Keep every sprite in the same pixel format so they can be compared byte for byte.
         self.sprites_to_add.push(Rect {
             animation: animation_name.clone(),
             frame_index: idx,
-            image: image,
+            image: DynamicImage::ImageRgba8(img.to_rgba8()),
             x: 0,
             y: 0,
-            id: Uuid::new_v4(),
+            page: 0,
+            rotated: false,
+            layers: layers
+                .into_iter()
+                .map(|(name, layer)| (name, DynamicImage::ImageRgba8(layer.to_rgba8())))
+                .collect(),
         });
     }
 
-    pub fn save(&mut self, path: &PathBuf) -> Result<(), String> {
-        if path.extension().is_some() {
-            return Err(format!(
-                "Path {:?} should not have an extension when saving a sprite sheet",
-                path
-            ));
-        }
+    /// Crop every sprite according to the trim mode and calculate the offsets of its frame.
+    fn trim_sprites(&mut self, trim: TrimMode) -> Vec<Rect> {
+        let threshold = self.options.alpha_threshold;
+        let mut bounds = self
+            .sprites_to_add
+            .iter()
+            .map(|r| match trim {
+                TrimMode::None => Some(Bounds::of_image(&r.image)),
+                TrimMode::AlphaThreshold | TrimMode::AnimationUnion => {
+                    Bounds::of_visible_pixels(&r.image, threshold)
+                }
+            })
+            .collect::<Vec<_>>();
 
-        // We'll pack the atlas by using the naive algorithm of sorting by height.
-        // I got better things to do.
-        // https://www.david-colson.com/2020/03/10/exploring-rect-packing.html
+        // Every frame of an animation is cropped to the same box so they don't jitter
+        if trim == TrimMode::AnimationUnion {
+            let mut unions: BTreeMap<&str, Bounds> = BTreeMap::new();
+            for (rect, bounds) in self.sprites_to_add.iter().zip(bounds.iter()) {
+                if let Some(b) = bounds {
+                    unions
+                        .entry(rect.animation.as_str())
+                        .and_modify(|u| *u = u.union(b))
+                        .or_insert(*b);
+                }
+            }
 
-        // Sort rectangles by img height
-        self.sprites_to_add
-            .sort_by(|a, b| b.image.height().cmp(&a.image.height()));
+            for (rect, bounds) in self.sprites_to_add.iter().zip(bounds.iter_mut()) {
+                if let Some(union) = unions.get(rect.animation.as_str()) {
+                    *bounds = union.clamp_to(&rect.image);
+                }
+            }
+        }
 
-        let max_columns = (self.sprites_to_add.len() as f32).sqrt().ceil() as u32;
+        let mut trimmed = vec![];
+        for (rect, bounds) in self.sprites_to_add.iter().zip(bounds) {
+            let img = &rect.image;
 
-        // Get average width and height of all images
-        let avg_width = self
-            .sprites_to_add
-            .iter()
-            .map(|r| r.image.width())
-            .sum::<u32>()
-            / self.sprites_to_add.len() as u32;
+            // crop image and its layers
+            let (cropped, top_left_offset_x, top_left_offset_y) = match bounds {
+                Some(b) => (
+                    rect.crop(b.min_x, b.min_y, b.width(), b.height()),
+                    b.min_x,
+                    b.min_y,
+                ),
+                None => {
+                    // Pretty weak but we'll just return an empty image.
+                    // This way we can still add the frame to the sheet
+                    // and the real edge case is that space will show up
+                    // the right size in font maps.
+                    let w = img.width() / 2;
+                    let h = img.height() / 2;
 
-        let avg_height = self
-            .sprites_to_add
-            .iter()
-            .map(|r| r.image.height())
-            .sum::<u32>()
-            / self.sprites_to_add.len() as u32;
+                    let empty = Rect {
+                        image: DynamicImage::new_rgba8(w, h),
+                        layers: rect
+                            .layers
+                            .keys()
+                            .map(|name| (name.clone(), DynamicImage::new_rgba8(w, h)))
+                            .collect(),
+                        ..rect.clone()
+                    };
+                    (empty, w, h)
+                }
+            };
+            let image = &cropped.image;
 
-        // Scale width to be a power of 2 that could contain roughly a quarter of the sprites
-        let target_width = avg_width * max_columns;
-        let mut width = 2;
-        while width < target_width {
-            width *= 2;
-        }
+            // Calculate offsets for drawing a centered image
+            let frame = &mut self.sheet.sprites.get_mut(&rect.animation).unwrap()[rect.frame_index];
+            frame.top_left_offset_x = top_left_offset_x;
+            frame.top_left_offset_y = top_left_offset_y;
+            frame.width = image.width();
+            frame.height = image.height();
+            frame.center_offset_x = (frame.original_width / 2) as i32 - (top_left_offset_x as i32);
+            frame.center_offset_y = (frame.original_height / 2) as i32 - (top_left_offset_y as i32);
 
-        let mut height = 2;
-        while height < avg_height {
-            height *= 2;
+            trimmed.push(cropped);
         }
 
-        // TODO: this is where I need to figure shit out.
+        trimmed
+    }
 
-        let mut sprites_to_add = self.sprites_to_add.clone();
-        // Find any duplicates
+    /// Find any duplicates by hashing each image, then confirming the pixels actually match.
+    /// If there's a tolerance, frames of the same original size that are close enough are merged as well.
+    /// They're compared where they sit in the frame, so crops that are a pixel apart still match,
+    /// and a merged frame takes the crop and offsets of the frame it reuses.
+    /// Returns the unique sprites and the frames that reuse them.
+    fn deduplicate(&mut self, trimmed: &[Rect]) -> (Vec<Rect>, Vec<DuplicateImageRect>) {
+        let tolerance = self.options.dedup_tolerance;
+        let mut sprites_to_add: Vec<Rect> = vec![];
         let mut duplicates: Vec<DuplicateImageRect> = vec![];
+        let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut same_size: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        let mut bytes_saved = 0;
 
-        for i in sprites_to_add.len()..0 {
-            if i == 0 {
-                break;
-            }
+        for rect in trimmed.iter() {
+            let candidates = hashes.entry(hash_image(&rect.image)).or_default();
+            let exact = candidates
+                .iter()
+                .find(|&&i| sprites_to_add[i].same_pixels(rect))
+                .copied();
 
-            for j in 0..i {
-                if sprites_to_add[i].image == sprites_to_add[j].image {
-                    duplicates.push(DuplicateImageRect::from_rect(
-                        &sprites_to_add[i],
-                        sprites_to_add[j].id.clone(),
-                    ));
+            let frame = self.frame(rect);
+            let size = (frame.original_width, frame.original_height);
+            let near = if exact.is_none() && tolerance > 0.0 {
+                same_size.get(&size).and_then(|indexes| {
+                    indexes.iter().find_map(|&i| {
+                        let other = &sprites_to_add[i];
+                        self.near_error(other, rect, tolerance)
+                            .map(|error| (i, error))
+                    })
+                })
+            } else {
+                None
+            };
+
+            if let Some((i, error)) = near {
+                let reference = &sprites_to_add[i];
+                println!(
+                    "Merged {} frame {} into {} frame {} (error {:.2})",
+                    rect.animation,
+                    rect.frame_index,
+                    reference.animation,
+                    reference.frame_index,
+                    error
+                );
+            }
 
-                    sprites_to_add.remove(i);
+            match (exact, near) {
+                (Some(i), _) => {
+                    duplicates.push(DuplicateImageRect::from_rect(rect, i, None));
+                    bytes_saved += rect.image.width() as usize * rect.image.height() as usize * 4;
+                }
+                (None, Some((i, error))) => {
+                    duplicates.push(DuplicateImageRect::from_rect(rect, i, Some(error)));
+                    bytes_saved += rect.image.width() as usize * rect.image.height() as usize * 4;
+                }
+                (None, None) => {
+                    candidates.push(sprites_to_add.len());
+                    same_size
+                        .entry(size)
+                        .or_default()
+                        .push(sprites_to_add.len());
+                    sprites_to_add.push(rect.clone());
                 }
             }
         }
 
-        // Now go through and pack all the rectangles by setting x and y
-        let mut x_pos = 0;
-        let mut y_pos = 0;
-        let mut largest_height_this_row = 0;
-        for rect in sprites_to_add.iter_mut() {
-            if x_pos + rect.image.width() > width {
-                x_pos = 0;
-                y_pos += largest_height_this_row;
-                largest_height_this_row = 0;
-            }
+        // A merged frame shows the pixels of its reference, so it has to be cropped the same way
+        for dup in duplicates.iter().filter(|dup| dup.error.is_some()) {
+            let reference = &sprites_to_add[dup.reference];
+            let trim = self.frame(reference).clone();
+            let frame = &mut self.sheet.sprites.get_mut(&dup.animation).unwrap()[dup.frame_index];
+            frame.top_left_offset_x = trim.top_left_offset_x;
+            frame.top_left_offset_y = trim.top_left_offset_y;
+            frame.width = trim.width;
+            frame.height = trim.height;
+            frame.center_offset_x = trim.center_offset_x;
+            frame.center_offset_y = trim.center_offset_y;
+        }
 
-            if y_pos + rect.image.height() > height {
-                height *= 2;
-            }
+        println!(
+            "Deduplicated {} of {} frames, saving {} bytes",
+            duplicates.len(),
+            trimmed.len(),
+            bytes_saved
+        );
 
-            rect.x = x_pos;
-            rect.y = y_pos;
+        (sprites_to_add, duplicates)
+    }
 
-            x_pos += rect.image.width();
+    /// The frame a trimmed rect belongs to.
+    fn frame(&self, rect: &Rect) -> &Frame {
+        &self.sheet.sprites[&rect.animation][rect.frame_index]
+    }
 
-            if rect.image.height() > largest_height_this_row {
-                largest_height_this_row = rect.image.height();
+    /// The mean error between two trimmed frames if it's at most `tolerance`, comparing them
+    /// where they sit in their frames on the union of both crops.
+    fn near_error(&self, a: &Rect, b: &Rect, tolerance: f32) -> Option<f32> {
+        let (frame_a, frame_b) = (self.frame(a), self.frame(b));
+        let crop_a = Bounds::of_crop(frame_a)?;
+        let crop_b = Bounds::of_crop(frame_b)?;
+        let union = crop_a.union(&crop_b);
+        let error = |a: &DynamicImage, b: &DynamicImage| {
+            mean_error(
+                &crop_a.place(a, &union),
+                &crop_b.place(b, &union),
+                tolerance,
+            )
+        };
+
+        // Layers have to be close enough too, but only the colour error is reported
+        let layers_match = a.layers.len() == b.layers.len()
+            && a.layers.iter().all(|(name, layer)| {
+                b.layers
+                    .get(name)
+                    .is_some_and(|l| error(layer, l).is_some())
+            });
+        error(&a.image, &b.image).filter(|_| layers_match)
+    }
+
+    /// Pack all the sprites into the smallest sheet the chosen packer can manage, setting their positions.
+    fn pack(&self, sprites_to_add: &mut [Rect]) -> Result<Vec<PackedPage>, String> {
+        // Each rectangle reserves room for the extruded border on every side and padding after it.
+        let extrude = self.options.extrude;
+        let margin = extrude * 2 + self.options.padding;
+        let sizes = sprites_to_add
+            .iter()
+            .map(|r| Size {
+                width: r.image.width() + margin,
+                height: r.image.height() + margin,
+            })
+            .collect::<Vec<_>>();
+        let pages = packing::pack(
+            self.options
+                .packer
+                .packer(self.options.allow_rotation)
+                .as_ref(),
+            &sizes,
+            self.options.max_size,
+            self.options.block_align,
+            self.options.sizing,
+        )?;
+
+        for (page_index, page) in pages.iter().enumerate() {
+            for (i, placement) in page.placements.iter() {
+                let rect = &mut sprites_to_add[*i];
+                rect.x = placement.x + extrude;
+                rect.y = placement.y + extrude;
+                rect.page = page_index;
+                rect.rotated = placement.rotated;
             }
         }
 
-        // Write rectangles to the image as well as frame data
-        let mut image = DynamicImage::new_rgba8(width, height);
-        self.sheet.width = width;
-        self.sheet.height = height;
+        Ok(pages)
+    }
 
-        for sprite in sprites_to_add.iter() {
-            image.copy_from(&sprite.image, sprite.x, sprite.y).unwrap();
+    /// Lay the sprites out in a grid of equally sized cells, with one animation per row, setting their positions.
+    fn grid(&mut self, sprites_to_add: &mut [Rect]) -> Result<Vec<PackedPage>, String> {
+        let extrude = self.options.extrude;
+        let margin = extrude * 2 + self.options.padding;
+        let block = self.options.block_align;
+        self.options.sizing.check_block(block)?;
+
+        // Cells are grown so each one starts on a block boundary
+        let cell_size = |size: u32| (size + margin).div_ceil(block) * block - margin;
+        let cell_width = cell_size(
+            sprites_to_add
+                .iter()
+                .map(|r| r.image.width())
+                .max()
+                .unwrap_or_default(),
+        );
+        let cell_height = cell_size(
+            sprites_to_add
+                .iter()
+                .map(|r| r.image.height())
+                .max()
+                .unwrap_or_default(),
+        );
+        let rows = self.sheet.sprites.len() as u32;
+        let columns = self
+            .sheet
+            .sprites
+            .values()
+            .map(|frames| frames.len())
+            .max()
+            .unwrap_or_default() as u32;
+
+        let Size { width, height } = self.options.sizing.round_up(
+            Size {
+                width: columns * (cell_width + margin),
+                height: rows * (cell_height + margin),
+            },
+            block,
+        );
+        if width > self.options.max_size || height > self.options.max_size {
+            return Err(format!(
+                "A {}x{} grid sheet does not fit in the maximum sheet size of {}",
+                width, height, self.options.max_size
+            ));
+        }
+
+        let row_of = self
+            .sheet
+            .sprites
+            .keys()
+            .enumerate()
+            .map(|(row, animation)| (animation.clone(), row as u32))
+            .collect::<HashMap<_, _>>();
+
+        for rect in sprites_to_add.iter_mut() {
+            rect.x = rect.frame_index as u32 * (cell_width + margin) + extrude;
+            rect.y = row_of[&rect.animation] * (cell_height + margin) + extrude;
+        }
+
+        self.sheet.grid = Some(Grid {
+            rows,
+            columns,
+            cell_width,
+            cell_height,
+            margin: extrude,
+            spacing: margin,
+        });
+
+        Ok(vec![PackedPage {
+            width,
+            height,
+            heuristic: "grid".to_string(),
+            placements: vec![],
+        }])
+    }
+
+    /// Build the polygon mesh of a placed sprite, with UVs for a page of the given size.
+    fn mesh(&self, sprite: &Rect, page_width: u32, page_height: u32) -> Mesh {
+        let vertices = mesh::outline(
+            &sprite.image,
+            self.options.alpha_threshold,
+            self.options.max_vertices as usize,
+        );
+
+        // Rotated sprites are turned 90 degrees clockwise, so (x, y) in the sprite is (height - y, x) in the sheet
+        let height = sprite.image.height() as f32;
+        let uvs = vertices
+            .iter()
+            .map(|[x, y]| {
+                let (sheet_x, sheet_y) = if sprite.rotated {
+                    (height - y, *x)
+                } else {
+                    (*x, *y)
+                };
+                [
+                    (sprite.x as f32 + sheet_x) / page_width as f32,
+                    (sprite.y as f32 + sheet_y) / page_height as f32,
+                ]
+            })
+            .collect();
+
+        Mesh {
+            triangles: mesh::triangulate(vertices.len()),
+            vertices,
+            uvs,
+        }
+    }
 
-            // write rectangle coordinates to frame
+    /// Write the position of each placed sprite to its frame, along with any frames that reuse it.
+    fn set_frame_positions(
+        &mut self,
+        sprites_to_add: &[Rect],
+        duplicates: &[DuplicateImageRect],
+        pages: &[PackedPage],
+    ) {
+        for sprite in sprites_to_add.iter() {
             let frame =
                 &mut self.sheet.sprites.get_mut(&sprite.animation).unwrap()[sprite.frame_index];
 
             frame.x = sprite.x;
             frame.y = sprite.y;
+            frame.page = sprite.page;
+            frame.rotated = sprite.rotated;
         }
 
-        // Update duplicate frames
         for dup in duplicates.iter() {
-            let rect = sprites_to_add
-                .iter()
-                .find(|r| r.id == dup.reference_rect_id)
-                .unwrap();
+            let rect = &sprites_to_add[dup.reference];
 
             let frame = &mut self.sheet.sprites.get_mut(&dup.animation).unwrap()[dup.frame_index];
 
             frame.x = rect.x;
             frame.y = rect.y;
+            frame.page = rect.page;
+            frame.rotated = rect.rotated;
+        }
+
+        // Build a tight polygon for every frame so less transparent space gets drawn
+        if self.options.polygon {
+            let meshes = sprites_to_add
+                .iter()
+                .map(|sprite| {
+                    let page = &pages[sprite.page];
+                    self.mesh(sprite, page.width, page.height)
+                })
+                .collect::<Vec<_>>();
+
+            for (sprite, mesh) in sprites_to_add.iter().zip(meshes.iter()) {
+                self.sheet.sprites.get_mut(&sprite.animation).unwrap()[sprite.frame_index].mesh =
+                    Some(mesh.clone());
+            }
+            for dup in duplicates.iter() {
+                self.sheet.sprites.get_mut(&dup.animation).unwrap()[dup.frame_index].mesh =
+                    Some(meshes[dup.reference].clone());
+            }
+        }
+    }
+
+    /// Cut every sprite into cells of `--dice` pixels, dropping empty cells and reusing identical ones.
+    /// Returns the unique cells and the cells that make up each sprite.
+    fn dice(&self, sprites_to_add: &[Rect]) -> (Vec<Rect>, Vec<Vec<CellRef>>) {
+        let size = self.options.dice;
+        let threshold = self.options.alpha_threshold;
+        let mut cells: Vec<Rect> = vec![];
+        let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut frame_cells = vec![];
+        let mut total = 0;
+        let mut empty = 0;
+
+        for sprite in sprites_to_add.iter() {
+            let mut used = vec![];
+            for y in (0..sprite.image.height()).step_by(size as usize) {
+                for x in (0..sprite.image.width()).step_by(size as usize) {
+                    let width = size.min(sprite.image.width() - x);
+                    let height = size.min(sprite.image.height() - y);
+                    let cell = sprite.crop(x, y, width, height);
+                    total += 1;
+
+                    if !cell
+                        .image
+                        .pixels()
+                        .any(|(_, _, pixel)| pixel[3] > threshold)
+                    {
+                        empty += 1;
+                        continue;
+                    }
+
+                    let candidates = hashes.entry(hash_image(&cell.image)).or_default();
+                    let index = match candidates.iter().find(|&&i| cells[i].same_pixels(&cell)) {
+                        Some(&i) => i,
+                        None => {
+                            candidates.push(cells.len());
+                            cells.push(Rect {
+                                x: 0,
+                                y: 0,
+                                page: 0,
+                                rotated: false,
+                                ..cell
+                            });
+                            cells.len() - 1
+                        }
+                    };
+                    used.push(CellRef {
+                        frame_x: x,
+                        frame_y: y,
+                        cell: index,
+                    });
+                }
+            }
+            frame_cells.push(used);
         }
 
+        println!(
+            "Diced {} frames into {} cells, dropping {} empty and packing {} unique cells",
+            sprites_to_add.len(),
+            total,
+            empty,
+            cells.len()
+        );
+
+        (cells, frame_cells)
+    }
+
+    /// Write the cells that make up each diced sprite to its frame, along with any frames that reuse it.
+    fn set_frame_cells(
+        &mut self,
+        sprites_to_add: &[Rect],
+        duplicates: &[DuplicateImageRect],
+        cells: &[Rect],
+        frame_cells: &[Vec<CellRef>],
+    ) {
+        let cells = frame_cells
+            .iter()
+            .map(|used| {
+                used.iter()
+                    .map(|r| {
+                        let cell = &cells[r.cell];
+                        Cell {
+                            frame_x: r.frame_x,
+                            frame_y: r.frame_y,
+                            width: cell.image.width(),
+                            height: cell.image.height(),
+                            x: cell.x,
+                            y: cell.y,
+                            page: cell.page,
+                            rotated: cell.rotated,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for (sprite, cells) in sprites_to_add.iter().zip(cells.iter()) {
+            self.sheet.sprites.get_mut(&sprite.animation).unwrap()[sprite.frame_index].cells =
+                cells.clone();
+        }
+        for dup in duplicates.iter() {
+            self.sheet.sprites.get_mut(&dup.animation).unwrap()[dup.frame_index].cells =
+                cells[dup.reference].clone();
+        }
+    }
+
+    pub fn save(&mut self, path: &PathBuf) -> Result<(), String> {
+        if path.extension().is_some() {
+            return Err(format!(
+                "Path {:?} should not have an extension when saving a sprite sheet",
+                path
+            ));
+        }
+
+        if self.options.dice > 0 && self.options.layout == Layout::Grid {
+            return Err("--dice can't be used with the grid layout".to_string());
+        }
+        if self.options.dice > 0 && self.options.polygon {
+            return Err("--dice can't be used with --polygon".to_string());
+        }
+        if let Some(format) = self.options.texture_format {
+            self.options.texture_container.check_format(format)?;
+        }
+        // Regions only stay apart in a mip level if they start and end on a multiple of its scale
+        let mip_scale = 1u64 << self.options.mip_levels.min(32);
+        if self.options.mip_levels > 0 && mip_scale > self.options.block_align as u64 {
+            return Err(format!(
+                "--mip-levels {} needs a --block-align of at least {}, or sprites share pixels in the smaller levels",
+                self.options.mip_levels, mip_scale
+            ));
+        }
+        if self.options.no_png
+            && self.options.texture_format.is_none()
+            && self.options.pixel_format.is_none()
+        {
+            return Err(
+                "--no-png needs a --texture-format or --pixel-format to write instead".to_string(),
+            );
+        }
+        if self.options.palette_size == 1 {
+            return Err("--palette-size needs room for a colour besides transparent".to_string());
+        }
+        if self.options.palette_size > 0 && self.options.palette_file.is_some() {
+            return Err("--palette-size can't be used with --palette-file".to_string());
+        }
+        for rect in self.sprites_to_add.iter() {
+            for (name, layer) in rect.layers.iter() {
+                if layer.dimensions() != rect.image.dimensions() {
+                    return Err(format!(
+                        "The {} layer of {} frame {} is {}x{}, but the frame is {}x{}",
+                        name,
+                        rect.animation,
+                        rect.frame_index,
+                        layer.width(),
+                        layer.height(),
+                        rect.image.width(),
+                        rect.image.height()
+                    ));
+                }
+            }
+        }
+        if self.options.fps <= 0.0 {
+            return Err("--fps must be above 0".to_string());
+        }
+        let playback = Playback {
+            fps: self.options.fps,
+            animation_fps: self
+                .options
+                .animation_fps
+                .iter()
+                .map(|a| (a.animation.clone(), a.fps))
+                .collect(),
+            no_loop: self.options.no_loop.iter().cloned().collect(),
+        };
+        playback.check_animations(&self.sheet)?;
+        let layer_names = self
+            .sprites_to_add
+            .iter()
+            .flat_map(|r| r.layers.keys().cloned())
+            .collect::<BTreeSet<_>>();
+
+        // The rectangles that get drawn into the pages; either whole frames or diced cells
+        let (placed, pages) = match self.options.layout {
+            Layout::Packed if self.options.dice > 0 => {
+                let trimmed = self.trim_sprites(self.options.trim);
+                let (sprites_to_add, duplicates) = self.deduplicate(&trimmed);
+                let (mut cells, frame_cells) = self.dice(&sprites_to_add);
+                let pages = self.pack(&mut cells)?;
+                self.set_frame_cells(&sprites_to_add, &duplicates, &cells, &frame_cells);
+                (cells, pages)
+            }
+            Layout::Packed => {
+                let trimmed = self.trim_sprites(self.options.trim);
+                let (mut sprites_to_add, duplicates) = self.deduplicate(&trimmed);
+                let pages = self.pack(&mut sprites_to_add)?;
+                self.set_frame_positions(&sprites_to_add, &duplicates, &pages);
+                (sprites_to_add, pages)
+            }
+            Layout::Grid => {
+                let mut sprites_to_add = self.trim_sprites(TrimMode::None);
+                let pages = self.grid(&mut sprites_to_add)?;
+                self.set_frame_positions(&sprites_to_add, &[], &pages);
+                (sprites_to_add, pages)
+            }
+        };
+        let extrude = self.options.extrude;
+
+        // Write rectangles to the images
+        let mut images = pages
+            .iter()
+            .map(|page| RgbaImage::new(page.width, page.height))
+            .collect::<Vec<_>>();
+        let mut layer_images = layer_names
+            .iter()
+            .map(|name| (name.clone(), images.clone()))
+            .collect::<BTreeMap<_, _>>();
+        self.sheet.width = pages.iter().map(|p| p.width).max().unwrap_or_default();
+        self.sheet.height = pages.iter().map(|p| p.height).max().unwrap_or_default();
+
+        for rect in placed.iter() {
+            let layers = std::iter::once((&mut images, &rect.image)).chain(
+                layer_images
+                    .iter_mut()
+                    .filter_map(|(name, pages)| Some((pages, rect.layers.get(name)?))),
+            );
+            for (pages, source) in layers {
+                let image = if rect.rotated {
+                    image::imageops::rotate90(source)
+                } else {
+                    source.to_rgba8()
+                };
+                pages[rect.page].copy_from(&image, rect.x, rect.y).unwrap();
+                image_utils::extrude(
+                    &mut pages[rect.page],
+                    rect.x,
+                    rect.y,
+                    image.width(),
+                    image.height(),
+                    extrude,
+                );
+            }
+        }
+
+        if self.options.alpha_bleed {
+            for image in images.iter_mut().chain(layer_images.values_mut().flatten()) {
+                image_utils::alpha_bleed(image);
+            }
+        }
+
+        // Every page shares one palette, so they can all be drawn with it
+        let colours = match (&self.options.palette_file, self.options.palette_size) {
+            (Some(file), _) => palette::load(file)?,
+            (None, 0) => vec![],
+            (None, size) => palette::generate(&images, size as usize, self.options.quantizer),
+        };
+        let indices = if colours.is_empty() {
+            vec![]
+        } else {
+            println!("Reduced the sheet to {} colours", colours.len());
+            images
+                .iter_mut()
+                .map(|image| palette::remap(image, &colours, self.options.dither))
+                .collect::<Vec<_>>()
+        };
+        self.sheet.palette = colours;
+
+        // Downsample each sprite and its extruded border on its own, so neighbours don't bleed in
+        let mut mips = images
+            .iter()
+            .enumerate()
+            .map(|(page, image)| {
+                let regions = placed
+                    .iter()
+                    .filter(|r| r.page == page && r.image.width() > 0 && r.image.height() > 0)
+                    .map(|r| {
+                        let (width, height) = if r.rotated {
+                            (r.image.height(), r.image.width())
+                        } else {
+                            (r.image.width(), r.image.height())
+                        };
+                        Region {
+                            x: r.x - extrude,
+                            y: r.y - extrude,
+                            width: width + extrude * 2,
+                            height: height + extrude * 2,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let levels = self
+                    .options
+                    .mip_levels
+                    .min(mipmap::full_chain_length(image.width(), image.height()));
+
+                mipmap::generate(image, &regions, levels)
+            })
+            .collect::<Vec<_>>();
+
+        // Mipmaps are filtered with straight alpha, so this comes after them
+        if self.options.premultiply {
+            for image in images.iter_mut().chain(mips.iter_mut().flatten()) {
+                image_utils::premultiply(image);
+            }
+            for colour in self.sheet.palette.iter_mut() {
+                image_utils::premultiply_pixel(colour);
+            }
+        }
+        self.sheet.premultiplied = self.options.premultiply;
+
         // Create directory if it doesn't exist
         if !path.is_dir() {
-            std::fs::create_dir_all(&path).unwrap();
+            std::fs::create_dir_all(path).unwrap();
         }
 
-        let path = path.join(&self.sheet.name);
+        // Single page sheets keep the plain name, otherwise each page is numbered
+        let names = (0..pages.len())
+            .map(|i| {
+                if pages.len() == 1 {
+                    self.sheet.name.clone()
+                } else {
+                    format!("{}_{}", self.sheet.name, i)
+                }
+            })
+            .collect::<Vec<_>>();
+        self.sheet.pages = pages
+            .iter()
+            .zip(names.iter())
+            .enumerate()
+            .map(|(i, (page, name))| {
+                let texture = self.options.texture_format.map(|format| Texture {
+                    file: format!("{}.{}", name, self.options.texture_container.extension()),
+                    format: format.name().to_string(),
+                    levels: mips[i].len() as u32 + 1,
+                });
+                let pixels = self.options.pixel_format.map(|format| RawPixels {
+                    file: format!("{}_{}.bin", name, format.name()),
+                    preview: (!self.options.no_png)
+                        .then(|| format!("{}_{}.png", name, format.name())),
+                    format: format.name().to_string(),
+                    levels: mips[i].len() as u32 + 1,
+                });
+                let mipmaps = if self.options.no_png {
+                    vec![]
+                } else {
+                    (1..=mips[i].len())
+                        .map(|level| format!("{}_mip{}.png", name, level))
+                        .collect()
+                };
+                let layers = layer_names
+                    .iter()
+                    .map(|layer| (layer.clone(), format!("{}_{}.png", name, layer)))
+                    .collect();
+                let image = match (&texture, &pixels) {
+                    (Some(texture), _) if self.options.no_png => texture.file.clone(),
+                    (_, Some(pixels)) if self.options.no_png => pixels.file.clone(),
+                    _ => format!("{}.png", name),
+                };
+                Page {
+                    image,
+                    width: page.width,
+                    height: page.height,
+                    packing_heuristic: page.heuristic.clone(),
+                    texture,
+                    pixels,
+                    mipmaps,
+                    layers,
+                }
+            })
+            .collect();
+
+        // Export before writing anything, in case a format can't describe the sheet
+        let exports = self
+            .options
+            .formats
+            .iter()
+            .map(|format| format.exporter(&playback).export(&self.sheet))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Save images
+        for (i, ((page, image), mips)) in self
+            .sheet
+            .pages
+            .iter()
+            .zip(images.iter())
+            .zip(mips.iter())
+            .enumerate()
+        {
+            if !self.options.no_png {
+                let img_path = path.join(&page.image);
+                match indices.get(i) {
+                    Some(indices) => palette::save_indexed(
+                        &img_path,
+                        image.width(),
+                        image.height(),
+                        indices,
+                        &self.sheet.palette,
+                    )?,
+                    None => image.save(&img_path).unwrap(),
+                }
+
+                println!("Saved sprite sheet to {:?}", img_path);
+
+                for (file, mip) in page.mipmaps.iter().zip(mips.iter()) {
+                    mip.save(path.join(file)).unwrap();
+                }
+            }
+
+            if let (Some(format), Some(texture)) = (self.options.texture_format, &page.texture) {
+                let texture_path = path.join(&texture.file);
+                texture::write(
+                    &texture_path,
+                    self.options.texture_container,
+                    format,
+                    image.width(),
+                    image.height(),
+                    &std::iter::once(image)
+                        .chain(mips.iter())
+                        .map(|level| texture::encode(level, format))
+                        .collect::<Vec<_>>(),
+                    self.sheet.premultiplied,
+                )?;
+
+                println!("Saved {} texture to {:?}", texture.format, texture_path);
+            }
+
+            if let (Some(format), Some(pixels)) = (self.options.pixel_format, &page.pixels) {
+                // Every level is reduced and stored one after the other, largest first
+                let mut bytes = vec![];
+                for (level, image) in std::iter::once(image).chain(mips.iter()).enumerate() {
+                    let (reduced, level_bytes) =
+                        pixel_format::quantize(image, format, self.options.dither);
+                    bytes.extend(level_bytes);
+
+                    if let (0, Some(preview)) = (level, &pixels.preview) {
+                        reduced.save(path.join(preview)).unwrap();
+                    }
+                }
 
-        // Save image
-        let img_path = format!("{}.png", path.as_os_str().to_str().unwrap());
-        image.save(&img_path).unwrap();
+                let pixels_path = path.join(&pixels.file);
+                std::fs::write(&pixels_path, bytes)
+                    .map_err(|e| format!("Unable to write {:?}: {}", pixels_path, e))?;
+
+                println!("Saved {} pixels to {:?}", pixels.format, pixels_path);
+            }
+
+            // Layers only have PNGs, so they're written even with --no-png
+            for (layer, file) in page.layers.iter() {
+                let layer_path = path.join(file);
+                layer_images[layer][i].save(&layer_path).unwrap();
+
+                println!("Saved {} layer to {:?}", layer, layer_path);
+            }
+
+            println!("Width: {}, Height: {}", page.width, page.height);
+        }
 
         // Save json
+        let path = path.join(&self.sheet.name);
         let json_path = format!("{}.json", path.as_os_str().to_str().unwrap());
         let json = serde_json::to_string_pretty(&self.sheet).unwrap();
         std::fs::write(&json_path, json).unwrap();
 
         println!("Saved JSON to {:?}", json_path);
-        println!("Saved sprite sheet to {:?}", img_path);
-        println!("Width: {}, Height: {}", width, height);
+
+        for file in exports.iter().flatten() {
+            let export_path = path.with_file_name(&file.name);
+            std::fs::write(&export_path, &file.contents)
+                .map_err(|e| format!("Unable to write {:?}: {}", export_path, e))?;
+
+            println!("Saved atlas to {:?}", export_path);
+        }
 
         Ok(())
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct SpriteSheet {
-    /// The width of the sprite sheet
+    /// The width of the sprite sheet. If there are multiple pages, this is the widest page.
     pub width: u32,
-    /// The height of the sprite sheet
+    /// The height of the sprite sheet. If there are multiple pages, this is the tallest page.
     pub height: u32,
     /// The name of the sprite sheet
     pub name: String,
+    /// The images that make up the sprite sheet
+    #[serde(default)]
+    pub pages: Vec<Page>,
+    /// The grid the sprites were laid out in, if the grid layout was used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid: Option<Grid>,
+    /// The red, green, blue and alpha of each colour the indexed page images use, by index.
+    /// The first colour is always fully transparent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub palette: Vec<[u8; 4]>,
+    /// Whether the colour of every pixel has been multiplied by its alpha
+    #[serde(default)]
+    pub premultiplied: bool,
     /// The sprites in the sprite sheet
-    #[serde(serialize_with = "ordered_map")]
-    pub sprites: HashMap<String, Vec<Frame>>,
+    pub sprites: BTreeMap<String, Vec<Frame>>,
+}
+
+impl Serialize for SpriteSheet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SpriteSheet::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpriteSheet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut sheet = SpriteSheet::deserialize(deserializer)?;
+
+        // Sheets saved before pages were added are a single `{name}.png` the size of the sheet
+        if sheet.pages.is_empty() {
+            sheet.pages.push(Page {
+                image: format!("{}.png", sheet.name),
+                width: sheet.width,
+                height: sheet.height,
+                packing_heuristic: "shelf".to_string(),
+                texture: None,
+                pixels: None,
+                mipmaps: vec![],
+                layers: BTreeMap::new(),
+            });
+        }
+
+        Ok(sheet)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    /// The number of rows, one per animation
+    pub rows: u32,
+    /// The number of columns, enough for the longest animation
+    pub columns: u32,
+    /// The width of each cell
+    pub cell_width: u32,
+    /// The height of each cell
+    pub cell_height: u32,
+    /// The distance from the edge of the sheet to the first cell
+    pub margin: u32,
+    /// The distance between neighbouring cells
+    pub spacing: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    /// The file name of the page image, relative to the JSON file.
+    /// This is the compressed texture if PNGs weren't written.
+    pub image: String,
+    /// The width of the page
+    pub width: u32,
+    /// The height of the page
+    pub height: u32,
+    /// The packer and heuristic that produced the page
+    pub packing_heuristic: String,
+    /// The GPU compressed copy of the page, if one was written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<Texture>,
+    /// The 16 bit copy of the page, if one was written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixels: Option<RawPixels>,
+    /// The file names of the mip levels below the page image, largest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mipmaps: Vec<String>,
+    /// The file names of the extra layers with the same layout as the page image, like normal maps, by layer name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Texture {
+    /// The file name of the compressed texture, relative to the JSON file
+    pub file: String,
+    /// The compression format of the texture, e.g. `bc7`
+    pub format: String,
+    /// The number of mip levels in the texture, including the full size one
+    pub levels: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawPixels {
+    /// The file name of the raw pixels, relative to the JSON file.
+    /// Each pixel is a little endian 16 bit value, from left to right and then top to bottom.
+    pub file: String,
+    /// The file name of a PNG showing how the reduced page looks, if PNGs were written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    /// The pixel format, e.g. `rgb565`
+    pub format: String,
+    /// The number of mip levels stored one after the other in the file, including the full size one
+    pub levels: u32,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -308,29 +1366,676 @@ pub struct Frame {
     pub center_offset_x: i32,
     /// Value to offset the center of the frame
     pub center_offset_y: i32,
-    /// The x position of the frame in the sprite sheet
+    /// The x position of the frame in the sprite sheet. Unused if the frame was diced.
     pub x: u32,
-    /// The y position of the frame in the sprite sheet
+    /// The y position of the frame in the sprite sheet. Unused if the frame was diced.
     pub y: u32,
-    /// The width of the frame
+    /// The index of the page the frame is on
+    #[serde(default)]
+    pub page: usize,
+    /// Whether the frame was turned 90 degrees clockwise in the sprite sheet.
+    /// When set, the frame takes up `height` x `width` pixels in the sheet.
+    #[serde(default)]
+    pub rotated: bool,
+    /// The width of the frame, before any rotation
     pub width: u32,
-    /// The height of the frame
+    /// The height of the frame, before any rotation
     pub height: u32,
     /// The original width of the frame before cropping
     pub original_width: u32,
     /// The original height of the frame before cropping
     pub original_height: u32,
+    /// A convex polygon covering the visible pixels of the frame, if polygon output is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<Mesh>,
+    /// The cells that make up the frame, if dicing is enabled. Empty parts of the frame have no cell.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cell {
+    /// The x position of the cell in the cropped frame
+    pub frame_x: u32,
+    /// The y position of the cell in the cropped frame
+    pub frame_y: u32,
+    /// The width of the cell, before any rotation
+    pub width: u32,
+    /// The height of the cell, before any rotation
+    pub height: u32,
+    /// The x position of the cell in the sprite sheet
+    pub x: u32,
+    /// The y position of the cell in the sprite sheet
+    pub y: u32,
+    /// The index of the page the cell is on
+    pub page: usize,
+    /// Whether the cell was turned 90 degrees clockwise in the sprite sheet
+    pub rotated: bool,
 }
 
-/// For use with serde's [serialize_with] attribute
-/// https://stackoverflow.com/a/42723390
-fn ordered_map<S, K: Ord + Serialize, V: Serialize>(
-    value: &HashMap<K, V>,
-    serializer: S,
-) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let ordered: BTreeMap<_, _> = value.iter().collect();
-    ordered.serialize(serializer)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mesh {
+    /// The vertices of the polygon in pixels, relative to the top left of the cropped frame
+    pub vertices: Vec<[f32; 2]>,
+    /// The texture coordinates of each vertex, from 0 to 1 across the page
+    pub uvs: Vec<[f32; 2]>,
+    /// The indices of the vertices that make up each triangle
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Hash the dimensions and pixels of an image
+fn hash_image(image: &DynamicImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.width().hash(&mut hasher);
+    image.height().hash(&mut hasher);
+    image.as_bytes().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the mean per-channel difference between two images of the same size,
+/// or `None` if it's larger than `tolerance`.
+/// Parse a --dedup-tolerance, which is a mean per-channel difference so it has to be in 0-255.
+fn parse_dedup_tolerance(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(tolerance) if (0.0..=255.0).contains(&tolerance) => Ok(tolerance),
+        _ => Err(format!(
+            "Invalid tolerance '{}', expected a number from 0 to 255",
+            s
+        )),
+    }
+}
+
+fn mean_error(a: &DynamicImage, b: &DynamicImage, tolerance: f32) -> Option<f32> {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+
+    // Bail out as soon as the total error can't fit under the tolerance
+    let limit = (tolerance as f64 * a.len() as f64) as u64;
+    let mut total = 0u64;
+    for (a, b) in a.iter().zip(b.iter()) {
+        total += a.abs_diff(*b) as u64;
+        if total > limit {
+            return None;
+        }
+    }
+
+    Some(total as f32 / a.len() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Parser, ValueEnum};
+    use image::Rgba;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        sheet_options: SheetOptions,
+    }
+
+    fn sheet_options(args: &[&str]) -> SheetOptions {
+        TestArgs::parse_from(std::iter::once("test").chain(args.iter().copied())).sheet_options
+    }
+
+    /// Create a sprite with a coloured box in it, surrounded by transparent pixels.
+    fn sprite(seed: u32, width: u32, height: u32) -> DynamicImage {
+        let mut image = RgbaImage::new(width + 4, height + 4);
+        for y in 0..height {
+            for x in 0..width {
+                let colour = Rgba([
+                    (seed * 37 + x) as u8,
+                    (seed * 91 + y) as u8,
+                    (seed * 13) as u8,
+                    255,
+                ]);
+                image.put_pixel(x + 2, y + 2, colour);
+            }
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+
+    fn build_and_save(options: SheetOptions, path: &PathBuf) -> (Vec<u8>, Vec<u8>) {
+        let mut builder = SpriteSheetBuilder::new("Deterministic".to_string(), options);
+        for (i, animation) in ["walk", "idle", "attack", "jump"].iter().enumerate() {
+            for frame in 0..6u32 {
+                let seed = i as u32 * 7 + frame;
+                // Lots of equal sizes, so packers have to break ties
+                builder.add_sprite(
+                    animation.to_string(),
+                    sprite(seed, 8 + (seed % 3) * 8, 16 + (frame % 2) * 8),
+                );
+            }
+        }
+        // Repeat a frame so duplicates are part of the sheet too
+        builder.add_sprite("idle".to_string(), sprite(0, 8, 16));
+
+        let _ = std::fs::remove_dir_all(path);
+        builder.save(path).unwrap();
+
+        let png = std::fs::read(path.join("Deterministic.png")).unwrap();
+        let json = std::fs::read(path.join("Deterministic.json")).unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+
+        (png, json)
+    }
+
+    #[test]
+    fn diced_cells_rebuild_every_frame() {
+        let dir = std::env::temp_dir().join("asset-builder-dice");
+        let _ = std::fs::remove_dir_all(&dir);
+        let args = ["--dice", "4", "--allow-rotation", "--padding", "1"];
+        let mut builder = SpriteSheetBuilder::new("Diced".to_string(), sheet_options(&args));
+
+        // Frames that share most of their pixels, so plenty of cells are reused
+        let mut originals = vec![];
+        for frame in 0..4u32 {
+            let mut image = sprite(1, 13, 10).to_rgba8();
+            image.put_pixel(3 + frame, 5, Rgba([255, 0, 0, 255]));
+            originals.push(DynamicImage::ImageRgba8(image.clone()));
+            builder.add_sprite("walk".to_string(), DynamicImage::ImageRgba8(image));
+        }
+        builder.save(&dir).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Diced.json")).unwrap()).unwrap();
+        let page = image::open(dir.join("Diced.png")).unwrap().to_rgba8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for (frame, original) in sheet.sprites["walk"].iter().zip(originals.iter()) {
+            let mut rebuilt = RgbaImage::new(frame.width, frame.height);
+            for cell in frame.cells.iter() {
+                for y in 0..cell.height {
+                    for x in 0..cell.width {
+                        // Rotated cells are turned 90 degrees clockwise in the sheet
+                        let (sheet_x, sheet_y) = if cell.rotated {
+                            (cell.x + cell.height - 1 - y, cell.y + x)
+                        } else {
+                            (cell.x + x, cell.y + y)
+                        };
+                        rebuilt.put_pixel(
+                            cell.frame_x + x,
+                            cell.frame_y + y,
+                            *page.get_pixel(sheet_x, sheet_y),
+                        );
+                    }
+                }
+            }
+
+            let expected = original
+                .crop_imm(
+                    frame.top_left_offset_x,
+                    frame.top_left_offset_y,
+                    frame.width,
+                    frame.height,
+                )
+                .to_rgba8();
+            assert!(rebuilt == expected, "a diced frame didn't rebuild");
+        }
+    }
+
+    #[test]
+    fn sheets_saved_before_pages_still_load() {
+        let json = r#"{
+            "width": 256,
+            "height": 128,
+            "name": "font_atlas",
+            "sprites": {
+                "A": [
+                    {
+                        "top_left_offset_x": 16,
+                        "top_left_offset_y": 16,
+                        "center_offset_x": 10,
+                        "center_offset_y": 24,
+                        "x": 161,
+                        "y": 47,
+                        "width": 23,
+                        "height": 35,
+                        "original_width": 64,
+                        "original_height": 64
+                    }
+                ]
+            }
+        }"#;
+
+        let sheet: SpriteSheet = serde_json::from_str(json).unwrap();
+
+        assert_eq!(sheet.pages.len(), 1);
+        assert_eq!(sheet.pages[0].image, "font_atlas.png");
+        assert_eq!((sheet.pages[0].width, sheet.pages[0].height), (256, 128));
+        let frame = &sheet.sprites["A"][0];
+        assert_eq!((frame.page, frame.rotated), (0, false));
+
+        // Saving it again writes the page out
+        let resaved = serde_json::to_string(&sheet).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SpriteSheet>(&resaved).unwrap(),
+            sheet
+        );
+        assert!(resaved.contains("\"pages\""));
+    }
+
+    #[test]
+    fn saving_the_same_sprites_twice_is_byte_identical() {
+        let dir = std::env::temp_dir().join("asset-builder-deterministic");
+
+        for packer in PackerType::value_variants() {
+            let packer = packer.to_possible_value().unwrap();
+            let args = [
+                "--packer",
+                packer.get_name(),
+                "--allow-rotation",
+                "--padding",
+                "1",
+            ];
+
+            let first = build_and_save(sheet_options(&args), &dir.join("first"));
+            let second = build_and_save(sheet_options(&args), &dir.join("second"));
+
+            assert!(
+                first == second,
+                "{} produced different output between runs",
+                packer.get_name()
+            );
+        }
+    }
+
+    #[test]
+    fn near_duplicates_are_compared_where_they_sit_in_the_frame() {
+        let mut builder = SpriteSheetBuilder::new(
+            "Near".to_string(),
+            sheet_options(&["--dedup-tolerance", "1"]),
+        );
+        builder.add_sprite("idle".to_string(), sprite(1, 8, 8));
+        // A faint anti-aliased pixel moves the trim bounds a pixel to the left
+        let mut held = sprite(1, 8, 8).to_rgba8();
+        held.put_pixel(1, 5, Rgba([0, 0, 0, 40]));
+        builder.add_sprite("idle".to_string(), DynamicImage::ImageRgba8(held));
+        builder.add_sprite("idle".to_string(), sprite(2, 8, 8));
+
+        let trimmed = builder.trim_sprites(TrimMode::AlphaThreshold);
+        assert_eq!(trimmed[1].image.width(), 9);
+        let (sprites, duplicates) = builder.deduplicate(&trimmed);
+
+        assert_eq!(sprites.len(), 2);
+        assert_eq!(duplicates.len(), 1);
+        let merged = &duplicates[0];
+        assert_eq!((merged.frame_index, merged.reference), (1, 0));
+        // One alpha of 40 over the 9x8 union of both crops
+        let error = merged.error.unwrap();
+        assert!((error - 40.0 / 288.0).abs() < 1e-4, "{}", error);
+
+        // The merged frame shows the first frame's pixels, so it takes its crop
+        let frames = &builder.sheet.sprites["idle"];
+        assert_eq!(
+            (
+                frames[1].top_left_offset_x,
+                frames[1].width,
+                frames[1].height
+            ),
+            (2, 8, 8)
+        );
+        assert_eq!(frames[1].center_offset_x, frames[0].center_offset_x);
+    }
+
+    #[test]
+    fn exact_duplicates_report_no_error() {
+        let mut builder = SpriteSheetBuilder::new("Exact".to_string(), sheet_options(&[]));
+        builder.add_sprite("idle".to_string(), sprite(1, 8, 8));
+        builder.add_sprite("idle".to_string(), sprite(1, 8, 8));
+
+        let trimmed = builder.trim_sprites(TrimMode::AlphaThreshold);
+        let (sprites, duplicates) = builder.deduplicate(&trimmed);
+
+        assert_eq!(sprites.len(), 1);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].error, None);
+    }
+
+    #[test]
+    fn dedup_tolerance_is_a_channel_difference() {
+        let parse = |tolerance: &str| {
+            TestArgs::try_parse_from(["test", "--dedup-tolerance", tolerance])
+                .map(|args| args.sheet_options.dedup_tolerance)
+        };
+
+        assert_eq!(parse("255").unwrap(), 255.0);
+        assert_eq!(parse("0.5").unwrap(), 0.5);
+        assert!(parse("300").is_err());
+        assert!(parse("-1").is_err());
+    }
+
+    #[test]
+    fn exact_duplicates_share_a_region_but_keep_their_offsets() {
+        let dir = std::env::temp_dir().join("asset-builder-duplicates");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut builder = SpriteSheetBuilder::new("Duplicates".to_string(), sheet_options(&[]));
+        builder.add_sprite("idle".to_string(), sprite(1, 8, 6));
+        builder.add_sprite("walk".to_string(), sprite(2, 8, 6));
+        // The same pixels further into a bigger frame
+        let mut moved = RgbaImage::new(16, 14);
+        image::imageops::replace(&mut moved, &sprite(1, 8, 6).to_rgba8(), 5, 3);
+        builder.add_sprite("walk".to_string(), DynamicImage::ImageRgba8(moved));
+        builder.save(&dir).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Duplicates.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let original = &sheet.sprites["idle"][0];
+        let duplicate = &sheet.sprites["walk"][1];
+        assert_eq!(
+            (duplicate.x, duplicate.y, duplicate.page),
+            (original.x, original.y, original.page)
+        );
+        assert_ne!(
+            (sheet.sprites["walk"][0].x, sheet.sprites["walk"][0].y),
+            (original.x, original.y)
+        );
+        assert_eq!((duplicate.width, duplicate.height), (8, 6));
+        assert_eq!(
+            (original.top_left_offset_x, original.top_left_offset_y),
+            (2, 2)
+        );
+        assert_eq!(
+            (duplicate.top_left_offset_x, duplicate.top_left_offset_y),
+            (7, 5)
+        );
+        assert_eq!(
+            (duplicate.original_width, duplicate.original_height),
+            (16, 14)
+        );
+        assert_eq!(
+            (duplicate.center_offset_x, duplicate.center_offset_y),
+            (1, 2)
+        );
+    }
+
+    #[test]
+    fn premultiplied_sheets_multiply_colour_by_alpha() {
+        let dir = std::env::temp_dir().join("asset-builder-premultiply");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut builder = SpriteSheetBuilder::new(
+            "Premultiplied".to_string(),
+            sheet_options(&["--premultiply"]),
+        );
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([200, 100, 50, 255]));
+        image.put_pixel(1, 0, Rgba([200, 100, 50, 128]));
+        builder.add_sprite("idle".to_string(), DynamicImage::ImageRgba8(image));
+        builder.save(&dir).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("Premultiplied.json")).unwrap())
+                .unwrap();
+        let page = image::open(dir.join("Premultiplied.png"))
+            .unwrap()
+            .to_rgba8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(json["premultiplied"], true);
+        let frame = &json["sprites"]["idle"][0];
+        let (x, y) = (frame["x"].as_u64().unwrap(), frame["y"].as_u64().unwrap());
+        assert_eq!(
+            *page.get_pixel(x as u32, y as u32),
+            Rgba([200, 100, 50, 255])
+        );
+        assert_eq!(
+            *page.get_pixel(x as u32 + 1, y as u32),
+            Rgba([100, 50, 25, 128])
+        );
+    }
+
+    #[test]
+    fn mip_levels_need_sprites_aligned_to_their_scale() {
+        let dir = std::env::temp_dir().join("asset-builder-mip-align");
+        let _ = std::fs::remove_dir_all(&dir);
+        let save = |args: &[&str]| {
+            let mut builder = SpriteSheetBuilder::new("Mips".to_string(), sheet_options(args));
+            builder.add_sprite("idle".to_string(), sprite(1, 5, 7));
+            builder.add_sprite("idle".to_string(), sprite(2, 9, 3));
+            builder.save(&dir)
+        };
+
+        let error = save(&["--mip-levels", "3"]).unwrap_err();
+        assert!(error.contains("--block-align of at least 8"), "{}", error);
+        assert!(save(&["--mip-levels", "3", "--block-align", "4"]).is_err());
+        assert!(save(&["--mip-levels", "40", "--block-align", "1024"]).is_err());
+        save(&["--mip-levels", "3", "--block-align", "8"]).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Mips.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        for frame in sheet.sprites["idle"].iter() {
+            assert_eq!((frame.x % 8, frame.y % 8), (0, 0));
+        }
+    }
+
+    #[test]
+    fn grid_cells_are_spaced_by_extrude_and_padding() {
+        let dir = std::env::temp_dir().join("asset-builder-grid");
+        let _ = std::fs::remove_dir_all(&dir);
+        let args = ["--layout", "grid", "--extrude", "2", "--padding", "1"];
+        let mut builder = SpriteSheetBuilder::new("Grid".to_string(), sheet_options(&args));
+        builder.add_sprite("attack".to_string(), sprite(1, 6, 4));
+        builder.add_sprite("attack".to_string(), sprite(2, 8, 3));
+        builder.add_sprite("attack".to_string(), sprite(3, 4, 9));
+        // Shorter than the number of columns
+        builder.add_sprite("idle".to_string(), sprite(4, 5, 5));
+        builder.save(&dir).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Grid.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Cells fit the biggest untrimmed frame, 12x13 including the transparent surround
+        assert_eq!(
+            sheet.grid,
+            Some(Grid {
+                rows: 2,
+                columns: 3,
+                cell_width: 12,
+                cell_height: 13,
+                margin: 2,
+                spacing: 5,
+            })
+        );
+        assert_eq!((sheet.width, sheet.height), (64, 64));
+
+        for (row, animation) in ["attack", "idle"].iter().enumerate() {
+            for (col, frame) in sheet.sprites[*animation].iter().enumerate() {
+                assert_eq!(frame.x, col as u32 * (12 + 5) + 2, "{} {}", animation, col);
+                assert_eq!(frame.y, row as u32 * (13 + 5) + 2, "{} {}", animation, col);
+                assert_eq!(
+                    (frame.width, frame.height),
+                    (frame.original_width, frame.original_height)
+                );
+                assert_eq!((frame.top_left_offset_x, frame.top_left_offset_y), (0, 0));
+            }
+        }
+        assert_eq!(sheet.sprites["idle"].len(), 1);
+    }
+
+    /// An image with an opaque box from `min` to `max` inclusive, surrounded by transparent pixels.
+    fn boxed(width: u32, height: u32, min: (u32, u32), max: (u32, u32)) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let inside = (min.0..=max.0).contains(&x) && (min.1..=max.1).contains(&y);
+            Rgba([
+                x as u8 * 10,
+                y as u8 * 10,
+                100,
+                if inside { 255 } else { 0 },
+            ])
+        }))
+    }
+
+    /// The crop of every frame of an animation, as its offset and size.
+    fn crops(builder: &SpriteSheetBuilder, animation: &str) -> Vec<(u32, u32, u32, u32)> {
+        builder.sheet.sprites[animation]
+            .iter()
+            .map(|f| (f.top_left_offset_x, f.top_left_offset_y, f.width, f.height))
+            .collect()
+    }
+
+    #[test]
+    fn animation_union_crops_every_frame_the_same() {
+        let mut builder = SpriteSheetBuilder::new("Union".to_string(), sheet_options(&[]));
+        builder.add_sprite("walk".to_string(), boxed(12, 10, (2, 3), (7, 5)));
+        builder.add_sprite("walk".to_string(), boxed(12, 10, (5, 1), (10, 3)));
+        // Smaller than the union of the other two, so it's clamped to its own edges
+        builder.add_sprite("walk".to_string(), boxed(8, 4, (1, 1), (1, 1)));
+        builder.add_sprite("idle".to_string(), boxed(12, 10, (4, 4), (5, 5)));
+
+        let trimmed = builder.trim_sprites(TrimMode::AnimationUnion);
+
+        assert_eq!(
+            crops(&builder, "walk"),
+            [(1, 1, 10, 5), (1, 1, 10, 5), (1, 1, 7, 3)]
+        );
+        assert_eq!(crops(&builder, "idle"), [(4, 4, 2, 2)]);
+        assert_eq!(
+            trimmed[1].image,
+            boxed(12, 10, (5, 1), (10, 3)).crop_imm(1, 1, 10, 5)
+        );
+        let centers = builder.sheet.sprites["walk"]
+            .iter()
+            .map(|f| (f.center_offset_x, f.center_offset_y))
+            .collect::<Vec<_>>();
+        assert_eq!(centers, [(5, 4), (5, 4), (3, 1)]);
+    }
+
+    #[test]
+    fn alpha_threshold_trims_faint_pixels() {
+        let mut image = boxed(12, 10, (4, 3), (7, 6)).to_rgba8();
+        image.put_pixel(1, 2, Rgba([0, 0, 0, 10]));
+        image.put_pixel(9, 8, Rgba([0, 0, 0, 11]));
+
+        for (threshold, crop) in [
+            ("0", (1, 2, 9, 7)),
+            ("10", (4, 3, 6, 6)),
+            ("11", (4, 3, 4, 4)),
+        ] {
+            let args = ["--alpha-threshold", threshold];
+            let mut builder = SpriteSheetBuilder::new("Faint".to_string(), sheet_options(&args));
+            builder.add_sprite("idle".to_string(), DynamicImage::ImageRgba8(image.clone()));
+            builder.trim_sprites(TrimMode::AlphaThreshold);
+
+            assert_eq!(crops(&builder, "idle"), [crop], "threshold {}", threshold);
+        }
+    }
+
+    #[test]
+    fn trim_none_keeps_the_whole_frame() {
+        let mut builder = SpriteSheetBuilder::new("Whole".to_string(), sheet_options(&[]));
+        builder.add_sprite("idle".to_string(), boxed(12, 10, (4, 3), (7, 6)));
+
+        let trimmed = builder.trim_sprites(TrimMode::None);
+
+        assert_eq!(crops(&builder, "idle"), [(0, 0, 12, 10)]);
+        let frame = &builder.sheet.sprites["idle"][0];
+        assert_eq!((frame.center_offset_x, frame.center_offset_y), (6, 5));
+        assert_eq!(trimmed[0].image, boxed(12, 10, (4, 3), (7, 6)));
+    }
+
+    #[test]
+    fn padding_leaves_empty_gaps_between_sprites() {
+        let dir = std::env::temp_dir().join("asset-builder-padding");
+        let _ = std::fs::remove_dir_all(&dir);
+        let args = ["--padding", "2", "--allow-rotation"];
+        let mut builder = SpriteSheetBuilder::new("Padded".to_string(), sheet_options(&args));
+        for (i, (width, height)) in [(6, 4), (5, 5), (3, 8), (7, 2), (4, 4)].iter().enumerate() {
+            let image = RgbaImage::from_pixel(*width, *height, Rgba([i as u8 * 40, 90, 30, 255]));
+            builder.add_sprite("idle".to_string(), DynamicImage::ImageRgba8(image));
+        }
+        builder.save(&dir).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Padded.json")).unwrap()).unwrap();
+        let page = image::open(dir.join("Padded.png")).unwrap().to_rgba8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The region each frame covers in the sheet, as left, top, right and bottom
+        let regions = sheet.sprites["idle"]
+            .iter()
+            .map(|f| {
+                let (width, height) = if f.rotated {
+                    (f.height, f.width)
+                } else {
+                    (f.width, f.height)
+                };
+                (f.x, f.y, f.x + width, f.y + height)
+            })
+            .collect::<Vec<_>>();
+        for (i, a) in regions.iter().enumerate() {
+            for b in regions[i + 1..].iter() {
+                let apart = a.2 + 2 <= b.0 || b.2 + 2 <= a.0 || a.3 + 2 <= b.1 || b.3 + 2 <= a.1;
+                assert!(apart, "{:?} and {:?} are closer than the padding", a, b);
+            }
+        }
+        for (x, y, pixel) in page.enumerate_pixels() {
+            let covered = regions
+                .iter()
+                .any(|r| (r.0..r.2).contains(&x) && (r.1..r.3).contains(&y));
+            assert_eq!(pixel[3] == 255, covered, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn rotated_frames_rebuild_from_the_page() {
+        let dir = std::env::temp_dir().join("asset-builder-rotated");
+        let _ = std::fs::remove_dir_all(&dir);
+        let args = ["--allow-rotation", "--polygon", "--max-size", "32"];
+        let mut builder = SpriteSheetBuilder::new("Rotated".to_string(), sheet_options(&args));
+        // Opaque gradients, so every pixel is different and each mesh is the whole frame.
+        // They only fit on one page if the tall ones are turned on their side.
+        let mut originals = vec![];
+        let sizes = [(28, 4), (4, 28)].repeat(3);
+        for (i, (width, height)) in sizes.iter().enumerate() {
+            let image = RgbaImage::from_fn(*width, *height, |x, y| {
+                Rgba([x as u8 * 9, y as u8 * 9, i as u8 * 30, 255])
+            });
+            originals.push(image.clone());
+            builder.add_sprite("spin".to_string(), DynamicImage::ImageRgba8(image));
+        }
+        builder.save(&dir).unwrap();
+
+        let sheet: SpriteSheet =
+            serde_json::from_slice(&std::fs::read(dir.join("Rotated.json")).unwrap()).unwrap();
+        let page = image::open(dir.join("Rotated.png")).unwrap().to_rgba8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let frames = &sheet.sprites["spin"];
+        assert!(frames.iter().any(|f| f.rotated), "nothing was rotated");
+        for (frame, original) in frames.iter().zip(originals.iter()) {
+            // Rotated frames are turned 90 degrees clockwise in the sheet
+            let sheet_position = |x: u32, y: u32| {
+                if frame.rotated {
+                    (frame.x + frame.height - 1 - y, frame.y + x)
+                } else {
+                    (frame.x + x, frame.y + y)
+                }
+            };
+            let rebuilt = RgbaImage::from_fn(frame.width, frame.height, |x, y| {
+                let (sheet_x, sheet_y) = sheet_position(x, y);
+                *page.get_pixel(sheet_x, sheet_y)
+            });
+            assert!(rebuilt == *original, "a frame didn't rebuild");
+
+            // Each vertex is a pixel corner, found at the matching corner of the region in the sheet
+            let mesh = frame.mesh.as_ref().unwrap();
+            assert_eq!(mesh.vertices.len(), 4);
+            for (vertex, uv) in mesh.vertices.iter().zip(mesh.uvs.iter()) {
+                let (x, y) = (vertex[0] as u32, vertex[1] as u32);
+                let corner = if frame.rotated {
+                    (frame.x + frame.height - y, frame.y + x)
+                } else {
+                    (frame.x + x, frame.y + y)
+                };
+                let uv = (uv[0] * page.width() as f32, uv[1] * page.height() as f32);
+                assert_eq!(uv, (corner.0 as f32, corner.1 as f32), "{:?}", vertex);
+            }
+        }
+    }
 }
@@ -1,10 +1,10 @@
 use clap::{Parser, ValueEnum};
 use core::panic;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use crate::spritesheet::SpriteSheetBuilder;
+use crate::spritesheet::{SheetOptions, SpriteSheetBuilder};
 
 /// The type of view the sprite sheet will be generated from
 #[derive(Parser, ValueEnum, Clone, Debug)]
@@ -40,7 +40,7 @@ pub fn get_blender_location() -> String {
     for option in options {
         if let Ok(output) = Command::new(&option).arg("--version").output() {
             let output = std::str::from_utf8(&output.stdout).unwrap();
-            if output.is_empty() == false {
+            if !output.is_empty() {
                 return option;
             }
         }
@@ -57,11 +57,29 @@ pub enum AnimationNaming {
     Custom(String),
 }
 
+/// Renders of extra layers, like normal maps, are named like their frame with `_{LAYER}` after the frame number.
+/// Returns the path of the frame the render belongs to and the layer name, or `None` if it's a frame itself.
+fn split_layer(path: &Path) -> Option<(PathBuf, String)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (prefix, rest) = stem.rsplit_once("FRAMENUMBER-")?;
+    let (number, layer) = rest.split_at(rest.find(|c: char| !c.is_ascii_digit())?);
+    let layer = layer.strip_prefix('_')?;
+    if layer.is_empty() || number.is_empty() {
+        return None;
+    }
+
+    Some((
+        path.with_file_name(format!("{}FRAMENUMBER-{}.png", prefix, number)),
+        layer.to_string(),
+    ))
+}
+
 /// Stitch together all renders in a directory into a single sprite sheet
 pub fn stitch_together_renders(
     blender_render_dir: &PathBuf,
     output_dir: &PathBuf,
     animation_naming: AnimationNaming,
+    sheet_options: SheetOptions,
 ) -> Result<(), String> {
     if !blender_render_dir.exists() {
         return Err(format!(
@@ -89,30 +107,37 @@ pub fn stitch_together_renders(
     }
 
     // List out all .png files in the output directory
-    let files = std::fs::read_dir(&blender_render_dir)
+    let mut files = std::fs::read_dir(blender_render_dir)
         .unwrap()
         .map(|res| res.map(|e| e.path()))
-        .filter(|res| res.is_ok())
-        .map(|res| res.unwrap())
+        .filter_map(|res| res.ok())
         .filter(|path| path.extension().unwrap() == "png")
         .collect::<Vec<_>>();
 
+    // Directory listings aren't ordered, so sort them to keep the output the same between runs
+    files.sort();
+
     // Helper function to extract the value of a key from a file name
     let find_value = |key: &str, contents: &str| -> String {
-        let idx = contents.find(&key).expect(&format!(
-            "Could not find '{}-name]' in file {}",
-            key, contents
-        ));
+        let idx = contents.find(key).unwrap_or_else(|| {
+            panic!("Could not find '{}-name]' in file {}", key, contents)
+        });
         let value = &contents[idx..];
         let value = value.split("]").collect::<Vec<&str>>()[0];
-        value.replace(&key, "").trim().to_string()
+        value.replace(key, "").trim().to_string()
     };
 
     // For each rendered file, extract the view type, file, animation, and perspective
     // then add it to the animations hashmap
-    let mut animations: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut animations: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut layer_files: BTreeMap<PathBuf, BTreeMap<String, PathBuf>> = BTreeMap::new();
     let mut name: Option<String> = None;
     for path in files {
+        if let Some((frame, layer)) = split_layer(&path) {
+            layer_files.entry(frame).or_default().insert(layer, path);
+            continue;
+        }
+
         let s = path.to_str().unwrap();
         let view_type = find_value("[VIEWTYPE-", s);
         let file = find_value("[FILE-", s);
@@ -135,16 +160,36 @@ pub fn stitch_together_renders(
         v.sort();
     }
 
+    // A layer without its frame is usually a render that failed or was deleted, so don't quietly drop it
+    for (frame, layers) in layer_files.iter() {
+        if !animations.values().any(|frames| frames.contains(frame)) {
+            let layer = layers.values().next().unwrap();
+            return Err(format!(
+                "Layer render {:?} has no frame, expected {:?}",
+                layer, frame
+            ));
+        }
+    }
+
     // Now that we have all animations, let's load them and determine the size of the sprite sheet
 
-    let mut animation_images = HashMap::new();
+    let mut animation_images = BTreeMap::new();
     for (animation, frames) in animations.iter() {
         let mut images = vec![];
 
         for frame in frames {
             let img = image::open(frame).unwrap();
-
-            images.push(img);
+            let layers: BTreeMap<_, _> = layer_files
+                .get(frame)
+                .map(|layers| {
+                    layers
+                        .iter()
+                        .map(|(layer, path)| (layer.clone(), image::open(path).unwrap()))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            images.push((img, layers));
         }
 
         animation_images.insert(animation.clone(), images);
@@ -155,12 +200,12 @@ pub fn stitch_together_renders(
         AnimationNaming::SingleObject => name.unwrap_or_default(),
         AnimationNaming::Custom(name) => name,
     };
-    let mut sprite_sheet = SpriteSheetBuilder::new(name);
+    let mut sprite_sheet = SpriteSheetBuilder::new(name, sheet_options);
 
     // Now for every animation image, add it to the sprite sheet
     for (animation, imgs) in animation_images.iter() {
-        for frame in imgs.iter() {
-            sprite_sheet.add_sprite(animation.clone(), frame.clone());
+        for (frame, layers) in imgs.iter() {
+            sprite_sheet.add_layered_sprite(animation.clone(), frame.clone(), layers.clone());
         }
     }
 
@@ -171,6 +216,7 @@ pub fn stitch_together_renders(
 }
 
 /// Render all animations in a blender file to the given directory
+#[allow(clippy::too_many_arguments)]
 pub fn render_animations(
     blender_file: PathBuf,
     script_path: PathBuf,
@@ -211,3 +257,78 @@ pub fn render_animations(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        sheet_options: SheetOptions,
+    }
+
+    const FRAME: &str =
+        "[FILE-Hero]_[VIEWTYPE-Sidescroller]_[ANIMATION-Run]_[PERSPECTIVE-0]_FRAMENUMBER-0001";
+
+    #[test]
+    fn layers_are_named_after_their_frame() {
+        let dir = Path::new("renders");
+        let layer = dir.join(format!("{}_normal.png", FRAME));
+        assert_eq!(
+            split_layer(&layer),
+            Some((dir.join(format!("{}.png", FRAME)), "normal".to_string()))
+        );
+        let layer = dir.join(format!("{}_emissive_mask.png", FRAME));
+        assert_eq!(
+            split_layer(&layer),
+            Some((
+                dir.join(format!("{}.png", FRAME)),
+                "emissive_mask".to_string()
+            ))
+        );
+
+        // Frames themselves, and underscores before the frame number, aren't layers
+        for name in [
+            format!("{}.png", FRAME),
+            format!("{}_.png", FRAME),
+            "[FILE-Hero]_[ANIMATION-Run_Fast]_FRAMENUMBER-0001.png".to_string(),
+            "[FILE-Hero]_FRAMENUMBER-_normal.png".to_string(),
+            "[FILE-Hero]_FRAMENUMBER-00a1_normal.png".to_string(),
+        ] {
+            assert_eq!(split_layer(&dir.join(&name)), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn layers_without_a_frame_are_an_error() {
+        let dir = std::env::temp_dir().join("asset-builder-orphan-layer");
+        let (renders, output) = (dir.join("renders"), dir.join("out"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&renders).unwrap();
+        std::fs::create_dir_all(&output).unwrap();
+
+        let image = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        image.save(renders.join(format!("{}.png", FRAME))).unwrap();
+        image
+            .save(renders.join(format!("{}_normal.png", FRAME)))
+            .unwrap();
+        let orphan = FRAME.replace("0001", "0002") + "_normal.png";
+        image.save(renders.join(&orphan)).unwrap();
+
+        let options = TestArgs::parse_from(["test"]).sheet_options;
+        let error = stitch_together_renders(
+            &renders,
+            &output,
+            AnimationNaming::SingleObject,
+            options.clone(),
+        )
+        .unwrap_err();
+        assert!(error.contains(&orphan), "{error}");
+
+        std::fs::remove_file(renders.join(&orphan)).unwrap();
+        stitch_together_renders(&renders, &output, AnimationNaming::SingleObject, options).unwrap();
+        assert!(output.join("Hero_normal.png").exists());
+    }
+}
@@ -1,6 +1,8 @@
+use crate::spritesheet::SheetOptions;
 use crate::spritesheet_gen::{render_animations, stitch_together_renders, ViewType};
 use std::path::PathBuf;
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     blender_file: PathBuf,
     output_directory: PathBuf,
@@ -9,6 +11,7 @@ pub fn run(
     view_type: ViewType,
     num_rotations: u32,
     animations: String,
+    sheet_options: SheetOptions,
 ) -> Result<(), String> {
     // Use
     // https://github.com/ericrobolson/BuilderGenerator
@@ -56,6 +59,7 @@ pub fn run(
         &blender_render_dir,
         &output_directory,
         crate::spritesheet_gen::AnimationNaming::SingleObject,
+        sheet_options,
     )?;
 
     Ok(())
@@ -1,4 +1,4 @@
-use crate::spritesheet::SpriteSheetBuilder;
+use crate::spritesheet::{SheetOptions, SpriteSheetBuilder};
 use image::{DynamicImage, Rgba};
 use rusttype::point;
 use std::{
@@ -12,6 +12,7 @@ pub fn run(
     file_extension: String,
     fontmap_directory: PathBuf,
     font_scale: f32,
+    sheet_options: SheetOptions,
 ) -> Result<(), String> {
     // Validate the TTF file
     if !ttf.exists() {
@@ -19,7 +20,7 @@ pub fn run(
     }
 
     // Validate the TTF file is a TTF file
-    if ttf.extension().unwrap().to_ascii_lowercase() != "ttf" {
+    if !ttf.extension().unwrap().eq_ignore_ascii_case("ttf") {
         return Err(format!("TTF file is not a TTF file: {:?}", ttf));
     }
 
@@ -36,7 +37,7 @@ pub fn run(
 
     // Create directory
     let parent = fontmap_directory.parent().unwrap();
-    std::fs::create_dir_all(&parent).unwrap();
+    std::fs::create_dir_all(parent).unwrap();
 
     // Font details
     let scale = rusttype::Scale::uniform(font_scale);
@@ -140,11 +141,11 @@ pub fn run(
     }
 
     // Assemble spritesheet
-    let mut spritesheet = SpriteSheetBuilder::new("font_atlas".to_string());
+    let mut spritesheet = SpriteSheetBuilder::new("font_atlas".to_string(), sheet_options);
 
     // Sort images for easier reading
     let mut images: Vec<_> = images.into_iter().collect();
-    images.sort_by(|a, b| a.0.cmp(&b.0));
+    images.sort_by_key(|a| a.0);
 
     // Add each image to the spritesheet
     for (character, image) in images {
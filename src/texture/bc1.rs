@@ -0,0 +1,159 @@
+use super::{endpoints, least_squares_endpoints, nearest, Block};
+
+/// Pixels with an alpha below this are written as transparent in BC1.
+const ALPHA_CUTOFF: u8 = 128;
+
+/// Quantize a colour to 5:6:5 bits.
+fn to_565(colour: &[f32; 3]) -> u16 {
+    let r = (colour[0] * 31.0 / 255.0).round() as u16;
+    let g = (colour[1] * 63.0 / 255.0).round() as u16;
+    let b = (colour[2] * 31.0 / 255.0).round() as u16;
+    (r << 11) | (g << 5) | b
+}
+
+/// Expand a 5:6:5 colour back to 8 bits per channel.
+fn from_565(colour: u16) -> [f32; 3] {
+    let r = (colour >> 11) & 31;
+    let g = (colour >> 5) & 63;
+    let b = colour & 31;
+    [
+        ((r << 3) | (r >> 2)) as f32,
+        ((g << 2) | (g >> 4)) as f32,
+        ((b << 3) | (b >> 2)) as f32,
+    ]
+}
+
+/// The colours a block can use. With 4 colours both endpoints and two colours between them,
+/// otherwise both endpoints and the colour halfway between them, as the 4th index is transparent.
+fn palette(c0: u16, c1: u16, four_colours: bool) -> Vec<[f32; 3]> {
+    let a = from_565(c0);
+    let b = from_565(c1);
+    let mix = |wa: f32, wb: f32, d: f32| [0, 1, 2].map(|c| ((a[c] * wa + b[c] * wb) / d).round());
+
+    if four_colours {
+        vec![a, b, mix(2.0, 1.0, 3.0), mix(1.0, 2.0, 3.0)]
+    } else {
+        vec![a, b, mix(1.0, 1.0, 2.0)]
+    }
+}
+
+/// How far along the line from the first endpoint to the second each palette entry is.
+const FOUR_COLOUR_WEIGHTS: [f32; 4] = [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0];
+const THREE_COLOUR_WEIGHTS: [f32; 3] = [0.0, 1.0, 0.5];
+
+/// Pick the palette entry for every colour, returning the indices and the total squared error.
+fn fit(colours: &[[f32; 3]], palette: &[[f32; 3]]) -> (Vec<usize>, f32) {
+    let mut error = 0.0;
+    let indices = colours
+        .iter()
+        .map(|c| {
+            let (i, e) = nearest(palette, c);
+            error += e;
+            i
+        })
+        .collect();
+    (indices, error)
+}
+
+/// Quantize a pair of endpoints, putting them in the order that selects the palette mode.
+fn quantize(start: &[f32; 3], end: &[f32; 3], four_colours: bool) -> (u16, u16) {
+    let (a, b) = (to_565(start), to_565(end));
+    // c0 > c1 selects 4 colours, c0 <= c1 selects 3 colours and transparent
+    if (a > b) == four_colours {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Find the endpoints and indices for a set of colours.
+fn encode_colours(colours: &[[f32; 3]], four_colours: bool) -> (u16, u16, Vec<usize>) {
+    let weights: &[f32] = if four_colours {
+        &FOUR_COLOUR_WEIGHTS
+    } else {
+        &THREE_COLOUR_WEIGHTS
+    };
+
+    let (start, end) = endpoints(colours);
+    let (c0, c1) = quantize(&start, &end, four_colours);
+    let (indices, error) = fit(colours, &palette(c0, c1, four_colours));
+
+    // Refit the endpoints to the chosen indices, keeping the result if it's better
+    let w = indices.iter().map(|&i| weights[i]).collect::<Vec<_>>();
+    let refit = least_squares_endpoints(colours, &w).map(|(start, end)| {
+        let (c0, c1) = quantize(&start, &end, four_colours);
+        let (indices, error) = fit(colours, &palette(c0, c1, four_colours));
+        (c0, c1, indices, error)
+    });
+    let (c0, c1, indices) = match refit {
+        Some((c0, c1, indices, refit_error)) if refit_error < error => (c0, c1, indices),
+        _ => (c0, c1, indices),
+    };
+
+    // 4 colour mode needs c0 > c1, so a solid colour uses 3 colour mode and its first index
+    if four_colours && c0 == c1 {
+        return (c0, c1, vec![0; colours.len()]);
+    }
+
+    (c0, c1, indices)
+}
+
+/// Write the endpoints and 2 bit indices of a block.
+fn pack(c0: u16, c1: u16, indices: &[u32; 16]) -> [u8; 8] {
+    let mut bits = 0u32;
+    for (i, &index) in indices.iter().enumerate() {
+        bits |= index << (i * 2);
+    }
+
+    let mut out = [0; 8];
+    out[0..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    out[4..8].copy_from_slice(&bits.to_le_bytes());
+    out
+}
+
+/// Compress a block to BC1. If `allow_transparent` is set, pixels with a low alpha are written as transparent black,
+/// otherwise the alpha is ignored as it's stored elsewhere, like in BC3.
+pub fn encode_block(block: &Block, allow_transparent: bool) -> [u8; 8] {
+    let transparent = allow_transparent && block.iter().any(|p| p[3] < ALPHA_CUTOFF);
+    let visible = (0..16)
+        .filter(|&i| !transparent || block[i][3] >= ALPHA_CUTOFF)
+        .collect::<Vec<_>>();
+
+    if visible.is_empty() {
+        // c0 <= c1 and every index 3 is fully transparent
+        return pack(0, 0, &[3; 16]);
+    }
+
+    let colours = visible
+        .iter()
+        .map(|&i| [0, 1, 2].map(|c| block[i][c] as f32))
+        .collect::<Vec<_>>();
+    let (c0, c1, fitted) = encode_colours(&colours, !transparent);
+
+    let mut indices = [3; 16];
+    for (&i, &index) in visible.iter().zip(fitted.iter()) {
+        indices[i] = index as u32;
+    }
+
+    pack(c0, c1, &indices)
+}
+
+/// Decompress a BC1 block. Colour blocks in BC3 always use 4 colours, so `allow_transparent` is unset for them.
+#[cfg(test)]
+pub fn decode_block(bytes: &[u8], allow_transparent: bool) -> Block {
+    let c0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let c1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let bits = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let four_colours = c0 > c1 || !allow_transparent;
+    let palette = palette(c0, c1, four_colours);
+
+    let mut block = [[0; 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let index = (bits >> (i * 2)) as usize & 3;
+        if let Some(colour) = palette.get(index) {
+            *pixel = [colour[0] as u8, colour[1] as u8, colour[2] as u8, 255];
+        }
+    }
+    block
+}
@@ -0,0 +1,67 @@
+use super::{bc1, Block};
+
+/// Compress the alpha of a block to 8 bytes, using both endpoints and 6 values between them.
+fn encode_alpha(block: &Block) -> [u8; 8] {
+    let a0 = block.iter().map(|p| p[3]).max().unwrap();
+    let a1 = block.iter().map(|p| p[3]).min().unwrap();
+
+    let mut out = [0; 8];
+    out[0] = a0;
+    out[1] = a1;
+    if a0 == a1 {
+        return out;
+    }
+
+    // a0 > a1 selects 8 values; a0, a1, then 6 steps from a0 to a1
+    let palette = (0..8u32)
+        .map(|i| match i {
+            0 => a0 as u32,
+            1 => a1 as u32,
+            _ => ((8 - i) * a0 as u32 + (i - 1) * a1 as u32) / 7,
+        })
+        .collect::<Vec<_>>();
+
+    let mut bits = 0u64;
+    for (i, p) in block.iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|&j| palette[j].abs_diff(p[3] as u32))
+            .unwrap();
+        bits |= (index as u64) << (i * 3);
+    }
+
+    out[2..8].copy_from_slice(&bits.to_le_bytes()[0..6]);
+    out
+}
+
+/// Compress a block to BC3; the alpha block followed by a BC1 colour block.
+pub fn encode_block(block: &Block) -> [u8; 16] {
+    let mut out = [0; 16];
+    out[0..8].copy_from_slice(&encode_alpha(block));
+    out[8..16].copy_from_slice(&bc1::encode_block(block, false));
+    out
+}
+
+/// Decompress a BC3 block.
+#[cfg(test)]
+pub fn decode_block(bytes: &[u8]) -> Block {
+    let (a0, a1) = (bytes[0] as u32, bytes[1] as u32);
+    let palette = (0..8u32)
+        .map(|i| match i {
+            0 => a0,
+            1 => a1,
+            _ if a0 > a1 => ((8 - i) * a0 + (i - 1) * a1) / 7,
+            6 => 0,
+            7 => 255,
+            _ => ((6 - i) * a0 + (i - 1) * a1) / 5,
+        })
+        .collect::<Vec<_>>();
+    let mut index_bytes = [0; 8];
+    index_bytes[0..6].copy_from_slice(&bytes[2..8]);
+    let bits = u64::from_le_bytes(index_bytes);
+
+    let mut block = bc1::decode_block(&bytes[8..16], false);
+    for (i, pixel) in block.iter_mut().enumerate() {
+        pixel[3] = palette[(bits >> (i * 3)) as usize & 7] as u8;
+    }
+    block
+}
@@ -0,0 +1,158 @@
+use super::{endpoints, least_squares_endpoints, nearest, Block};
+
+/// The weights of the 16 colours between the endpoints, out of 64.
+const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// An endpoint quantized to 7 bits per channel, plus a shared lowest bit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Endpoint {
+    colour: [u8; 4],
+    p_bit: u8,
+}
+
+impl Endpoint {
+    /// Quantize an endpoint, picking whichever lowest bit gets closest to it.
+    /// Fully opaque and fully transparent endpoints always keep their alpha exactly, as that's what shows the most.
+    fn quantize(colour: &[f32; 4]) -> Self {
+        let p_bits: &[u8] = match colour[3] {
+            a if a >= 254.5 => &[1],
+            a if a < 0.5 => &[0],
+            _ => &[0, 1],
+        };
+
+        p_bits
+            .iter()
+            .map(|&p_bit| Self {
+                colour: colour.map(|v| ((v - p_bit as f32) / 2.0).round().clamp(0.0, 127.0) as u8),
+                p_bit,
+            })
+            .min_by(|a, b| {
+                let error = |e: &Self| super::distance(&e.expand(), colour);
+                error(a).total_cmp(&error(b))
+            })
+            .unwrap()
+    }
+
+    /// The 8 bit colour of the endpoint.
+    fn expand(&self) -> [f32; 4] {
+        self.colour.map(|c| ((c << 1) | self.p_bit) as f32)
+    }
+}
+
+/// The 16 colours between two endpoints.
+fn palette(e0: &Endpoint, e1: &Endpoint) -> Vec<[f32; 4]> {
+    let a = e0.expand();
+    let b = e1.expand();
+    WEIGHTS
+        .iter()
+        .map(|&w| {
+            [0, 1, 2, 3]
+                .map(|c| ((64 - w) as f32 * a[c] + w as f32 * b[c] + 32.0) / 64.0)
+                .map(f32::floor)
+        })
+        .collect()
+}
+
+/// Pick the palette entry for every colour, returning the indices and the total squared error.
+fn fit(colours: &[[f32; 4]], e0: &Endpoint, e1: &Endpoint) -> ([u8; 16], f32) {
+    let palette = palette(e0, e1);
+    let mut indices = [0; 16];
+    let mut error = 0.0;
+    for (i, c) in colours.iter().enumerate() {
+        let (index, e) = nearest(&palette, c);
+        indices[i] = index as u8;
+        error += e;
+    }
+    (indices, error)
+}
+
+/// Writes values to a 128 bit block, lowest bit first.
+struct BitWriter {
+    bits: u128,
+    position: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u8, bits: u32) {
+        self.bits |= (value as u128) << self.position;
+        self.position += bits;
+    }
+}
+
+/// Compress a block to BC7 using mode 6; a single pair of 7777.1 RGBA endpoints and 4 bit indices.
+pub fn encode_block(block: &Block) -> [u8; 16] {
+    let colours = block.map(|p| p.map(|c| c as f32));
+
+    let (start, end) = endpoints(&colours);
+    let (mut e0, mut e1) = (Endpoint::quantize(&start), Endpoint::quantize(&end));
+    let (mut indices, error) = fit(&colours, &e0, &e1);
+
+    // Refit the endpoints to the chosen indices, keeping the result if it's better
+    let w = indices
+        .iter()
+        .map(|&i| WEIGHTS[i as usize] as f32 / 64.0)
+        .collect::<Vec<_>>();
+    if let Some((start, end)) = least_squares_endpoints(&colours, &w) {
+        let (r0, r1) = (Endpoint::quantize(&start), Endpoint::quantize(&end));
+        let (refit, refit_error) = fit(&colours, &r0, &r1);
+        if refit_error < error {
+            (e0, e1, indices) = (r0, r1, refit);
+        }
+    }
+
+    // The top bit of the first index isn't stored, so it has to be in the first half of the palette
+    if indices[0] >= 8 {
+        std::mem::swap(&mut e0, &mut e1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut writer = BitWriter {
+        bits: 0,
+        position: 0,
+    };
+    writer.write(1 << 6, 7);
+    for c in 0..4 {
+        writer.write(e0.colour[c], 7);
+        writer.write(e1.colour[c], 7);
+    }
+    writer.write(e0.p_bit, 1);
+    writer.write(e1.p_bit, 1);
+    for (i, &index) in indices.iter().enumerate() {
+        writer.write(index, if i == 0 { 3 } else { 4 });
+    }
+
+    writer.bits.to_le_bytes()
+}
+
+/// Decompress a BC7 block. Only mode 6 is supported, as that's the only one written.
+#[cfg(test)]
+pub fn decode_block(bytes: &[u8]) -> Block {
+    let bits = u128::from_le_bytes(bytes.try_into().unwrap());
+    let mut position = 0;
+    let mut read = |count: u32| {
+        let value = (bits >> position) as u8 & ((1u16 << count) - 1) as u8;
+        position += count;
+        value
+    };
+    assert_eq!(read(7), 1 << 6, "only mode 6 blocks can be decoded");
+
+    let mut e0 = Endpoint {
+        colour: [0; 4],
+        p_bit: 0,
+    };
+    let mut e1 = e0;
+    for c in 0..4 {
+        e0.colour[c] = read(7);
+        e1.colour[c] = read(7);
+    }
+    e0.p_bit = read(1);
+    e1.p_bit = read(1);
+
+    let palette = palette(&e0, &e1);
+    let mut block = [[0; 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let index = read(if i == 0 { 3 } else { 4 });
+        *pixel = palette[index as usize].map(|c| c as u8);
+    }
+    block
+}
@@ -0,0 +1,144 @@
+use super::TextureFormat;
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const DDS_ALPHA_MODE_PREMULTIPLIED: u32 = 2;
+
+/// Sprite sheets hold colours, so every format is written as sRGB.
+pub fn dxgi_format(format: TextureFormat) -> Result<u32, String> {
+    match format {
+        TextureFormat::Bc1 => Ok(72), // DXGI_FORMAT_BC1_UNORM_SRGB
+        TextureFormat::Bc3 => Ok(78), // DXGI_FORMAT_BC3_UNORM_SRGB
+        TextureFormat::Bc7 => Ok(99), // DXGI_FORMAT_BC7_UNORM_SRGB
+        TextureFormat::Etc2 => Err("ETC2 textures can't be written to DDS, use KTX2".to_string()),
+    }
+}
+
+/// Build a DDS file with the DX10 header from compressed mip levels, largest first.
+pub fn write(
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+    premultiplied: bool,
+) -> Result<Vec<u8>, String> {
+    let dxgi_format = dxgi_format(format)?;
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE;
+    let mut caps = DDSCAPS_TEXTURE;
+    if levels.len() > 1 {
+        flags |= DDSD_MIPMAPCOUNT;
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+
+    let mut header = vec![
+        124, // dwSize
+        flags,
+        height,
+        width,
+        levels.first().map(|l| l.len() as u32).unwrap_or_default(), // dwPitchOrLinearSize
+        0,                                                          // dwDepth
+        levels.len() as u32,
+    ];
+    header.extend([0; 11]); // dwReserved1
+
+    // The pixel format just points at the DX10 header
+    header.extend([32, DDPF_FOURCC, u32::from_le_bytes(*b"DX10"), 0, 0, 0, 0, 0]);
+    header.extend([caps, 0, 0, 0, 0]);
+
+    // DX10 header, with the alpha mode in the last field
+    let alpha_mode = if premultiplied {
+        DDS_ALPHA_MODE_PREMULTIPLIED
+    } else {
+        0
+    };
+    header.extend([
+        dxgi_format,
+        D3D10_RESOURCE_DIMENSION_TEXTURE2D,
+        0,
+        1,
+        alpha_mode,
+    ]);
+
+    let mut out = b"DDS ".to_vec();
+    for value in header {
+        out.extend(value.to_le_bytes());
+    }
+    for level in levels.iter() {
+        out.extend(level);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_describes_the_levels() {
+        for (format, dxgi) in [
+            (TextureFormat::Bc1, 72),
+            (TextureFormat::Bc3, 78),
+            (TextureFormat::Bc7, 99),
+        ] {
+            let block = format.block_bytes();
+            for levels in [
+                vec![vec![1; 2 * block]],
+                vec![vec![1; 2 * block], vec![2; block]],
+            ] {
+                for premultiplied in [false, true] {
+                    let file = write(format, 8, 4, &levels, premultiplied).unwrap();
+                    let name = format.name();
+
+                    assert_eq!(&file[..4], b"DDS ", "{name}");
+                    assert_eq!(u32_at(&file, 4), 124, "{name} dwSize");
+                    let flags = u32_at(&file, 8);
+                    assert_eq!(flags & DDSD_MIPMAPCOUNT != 0, levels.len() > 1, "{name}");
+                    assert_eq!(u32_at(&file, 12), 4, "{name} height");
+                    assert_eq!(u32_at(&file, 16), 8, "{name} width");
+                    assert_eq!(u32_at(&file, 20) as usize, 2 * block, "{name} linear size");
+                    assert_eq!(u32_at(&file, 28) as usize, levels.len(), "{name} mip count");
+
+                    // The pixel format points at the DX10 header
+                    assert_eq!(u32_at(&file, 76), 32, "{name}");
+                    assert_eq!(u32_at(&file, 80), DDPF_FOURCC, "{name}");
+                    assert_eq!(&file[84..88], b"DX10", "{name}");
+                    let caps = u32_at(&file, 108);
+                    assert_eq!(caps & DDSCAPS_MIPMAP != 0, levels.len() > 1, "{name}");
+
+                    assert_eq!(u32_at(&file, 128), dxgi, "{name}");
+                    assert_eq!(u32_at(&file, 132), D3D10_RESOURCE_DIMENSION_TEXTURE2D);
+                    assert_eq!(u32_at(&file, 140), 1, "{name} array size");
+                    let alpha_mode = if premultiplied {
+                        DDS_ALPHA_MODE_PREMULTIPLIED
+                    } else {
+                        0
+                    };
+                    assert_eq!(u32_at(&file, 144), alpha_mode, "{name}");
+
+                    // The levels follow the 148 byte header, largest first
+                    assert_eq!(file[148..], levels.concat()[..], "{name}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn etc2_is_refused() {
+        assert!(write(TextureFormat::Etc2, 4, 4, &[vec![0; 16]], false).is_err());
+    }
+}
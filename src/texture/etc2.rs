@@ -0,0 +1,266 @@
+use super::Block;
+
+/// The brightness offsets each ETC colour sub-block can choose from, in the order of the 2 bit pixel indices.
+const COLOUR_TABLES: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+/// The offsets each EAC alpha block can choose from, in the order of the 3 bit pixel indices.
+const ALPHA_TABLES: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// ETC stores pixel indices top to bottom and then left to right, so this is the bit of each pixel in a `Block`.
+fn pixel_bit(i: usize) -> usize {
+    let (x, y) = (i % 4, i / 4);
+    x * 4 + y
+}
+
+/// The best table and pixel indices for a sub-block with a given base colour.
+struct SubBlock {
+    table: u64,
+    indices: Vec<(usize, u64)>,
+    error: u32,
+}
+
+fn encode_sub_block(block: &Block, pixels: &[usize], base: [i32; 3]) -> SubBlock {
+    let mut best: Option<SubBlock> = None;
+
+    for (table, offsets) in COLOUR_TABLES.iter().enumerate() {
+        let mut indices = vec![];
+        let mut error = 0;
+        for &i in pixels.iter() {
+            let (index, e) = offsets
+                .iter()
+                .enumerate()
+                .map(|(index, offset)| {
+                    let e = (0..3)
+                        .map(|c| {
+                            let v = (base[c] + offset).clamp(0, 255);
+                            let d = v - block[i][c] as i32;
+                            (d * d) as u32
+                        })
+                        .sum::<u32>();
+                    (index, e)
+                })
+                .min_by_key(|&(_, e)| e)
+                .unwrap();
+            indices.push((i, index as u64));
+            error += e;
+        }
+
+        if best.as_ref().map(|b| error < b.error).unwrap_or(true) {
+            best = Some(SubBlock {
+                table: table as u64,
+                indices,
+                error,
+            });
+        }
+    }
+
+    best.unwrap()
+}
+
+/// The average colour of some pixels of a block.
+fn average(block: &Block, pixels: &[usize]) -> [f32; 3] {
+    let mut sum = [0.0; 3];
+    for &i in pixels.iter() {
+        for c in 0..3 {
+            sum[c] += block[i][c] as f32 / pixels.len() as f32;
+        }
+    }
+    sum
+}
+
+/// Compress the colour of a block using the ETC1 individual and differential modes, which every ETC2 decoder reads.
+fn encode_colour(block: &Block) -> u64 {
+    let mut best: Option<(u32, u64)> = None;
+
+    for flip in [false, true] {
+        // Either a left and right half, or a top and bottom half
+        let (first, second): (Vec<usize>, Vec<usize>) =
+            (0..16).partition(|&i| if flip { i / 4 < 2 } else { i % 4 < 2 });
+        let averages = [average(block, &first), average(block, &second)];
+
+        for differential in [false, true] {
+            let (bases, header) = if differential {
+                // A 5 bit colour and a 3 bit signed difference to the second colour
+                let q0 = averages[0].map(|v| (v * 31.0 / 255.0).round() as i32);
+                let q1 = averages[1].map(|v| (v * 31.0 / 255.0).round() as i32);
+                let delta = [0, 1, 2].map(|c| (q1[c] - q0[c]).clamp(-4, 3));
+                let q1 = [0, 1, 2].map(|c| q0[c] + delta[c]);
+
+                let expand = |q: [i32; 3]| q.map(|v| (v << 3) | (v >> 2));
+                let mut header = 0u64;
+                for c in 0..3 {
+                    header |= (q0[c] as u64) << (59 - c * 8);
+                    header |= ((delta[c] & 7) as u64) << (56 - c * 8);
+                }
+                ([expand(q0), expand(q1)], header)
+            } else {
+                // Two separate 4 bit colours
+                let q = averages.map(|a| a.map(|v| (v * 15.0 / 255.0).round() as i32));
+
+                let mut header = 0u64;
+                for (c, (first, second)) in q[0].iter().zip(q[1].iter()).enumerate() {
+                    header |= (*first as u64) << (60 - c * 8);
+                    header |= (*second as u64) << (56 - c * 8);
+                }
+                (q.map(|q| q.map(|v| v * 17)), header)
+            };
+
+            let subs = [
+                encode_sub_block(block, &first, bases[0]),
+                encode_sub_block(block, &second, bases[1]),
+            ];
+            let error = subs[0].error + subs[1].error;
+            if best.map(|(e, _)| error >= e).unwrap_or(false) {
+                continue;
+            }
+
+            let mut bits = header
+                | subs[0].table << 37
+                | subs[1].table << 34
+                | (differential as u64) << 33
+                | (flip as u64) << 32;
+            for sub in subs.iter() {
+                for &(i, index) in sub.indices.iter() {
+                    let bit = pixel_bit(i);
+                    bits |= (index >> 1) << (16 + bit);
+                    bits |= (index & 1) << bit;
+                }
+            }
+
+            best = Some((error, bits));
+        }
+    }
+
+    best.unwrap().1
+}
+
+/// Compress the alpha of a block to EAC; a base value, a multiplier and one of the offset tables.
+fn encode_alpha(block: &Block) -> u64 {
+    let alphas = block.map(|p| p[3] as i32);
+    let min = *alphas.iter().min().unwrap();
+    let max = *alphas.iter().max().unwrap();
+
+    // Table 13 has an offset of 0, so a solid alpha is exact
+    if min == max {
+        let index_of_zero = 4u64;
+        let mut bits = (min as u64) << 56 | 1 << 52 | 13 << 48;
+        for i in 0..16 {
+            bits |= index_of_zero << (45 - pixel_bit(i) * 3);
+        }
+        return bits;
+    }
+
+    let mut best: Option<(i32, u64)> = None;
+    for (table, offsets) in ALPHA_TABLES.iter().enumerate() {
+        let low = offsets[3];
+        let high = offsets[7];
+        let multiplier = ((max - min) as f32 / (high - low) as f32).round() as i32;
+
+        for multiplier in (multiplier - 1).max(1)..=(multiplier + 1).min(15) {
+            // Centre the range the table covers on the range of the block
+            let base = ((min + max) as f32 / 2.0 - (low + high) as f32 * multiplier as f32 / 2.0)
+                .round()
+                .clamp(0.0, 255.0) as i32;
+
+            let mut bits = (base as u64) << 56 | (multiplier as u64) << 52 | (table as u64) << 48;
+            let mut error = 0;
+            for (i, &alpha) in alphas.iter().enumerate() {
+                let (index, e) = offsets
+                    .iter()
+                    .enumerate()
+                    .map(|(index, offset)| {
+                        let d = (base + offset * multiplier).clamp(0, 255) - alpha;
+                        (index, d * d)
+                    })
+                    .min_by_key(|&(_, e)| e)
+                    .unwrap();
+                bits |= (index as u64) << (45 - pixel_bit(i) * 3);
+                error += e;
+            }
+
+            if best.map(|(e, _)| error < e).unwrap_or(true) {
+                best = Some((error, bits));
+            }
+        }
+    }
+
+    best.unwrap().1
+}
+
+/// Compress a block to ETC2 RGBA8; the EAC alpha block followed by the colour block, both big endian.
+pub fn encode_block(block: &Block) -> [u8; 16] {
+    let mut out = [0; 16];
+    out[0..8].copy_from_slice(&encode_alpha(block).to_be_bytes());
+    out[8..16].copy_from_slice(&encode_colour(block).to_be_bytes());
+    out
+}
+
+/// Decompress an ETC2 RGBA8 block. Only the individual and differential modes are supported, as they're the only ones written.
+#[cfg(test)]
+pub fn decode_block(bytes: &[u8]) -> Block {
+    let alpha = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+    let colour = u64::from_be_bytes(bytes[8..16].try_into().unwrap());
+    let field = |offset: u32, bits: u32| ((colour >> offset) & ((1 << bits) - 1)) as i32;
+
+    let differential = field(33, 1) == 1;
+    let flip = field(32, 1) == 1;
+    let bases = if differential {
+        let q0 = [59, 51, 43].map(|offset| field(offset, 5));
+        let delta = [56, 48, 40].map(|offset| (field(offset, 3) << 29) >> 29);
+        let q1 = [0, 1, 2].map(|c| q0[c] + delta[c]);
+        assert!(
+            q1.iter().all(|v| (0..32).contains(v)),
+            "only ETC1 modes can be decoded"
+        );
+        [q0, q1].map(|q| q.map(|v| (v << 3) | (v >> 2)))
+    } else {
+        [[60, 52, 44], [56, 48, 40]].map(|offsets| offsets.map(|offset| field(offset, 4) * 17))
+    };
+    let tables = [field(37, 3), field(34, 3)];
+
+    let base_alpha = (alpha >> 56) as i32;
+    let multiplier = (alpha >> 52) as i32 & 15;
+    let alpha_table = ALPHA_TABLES[(alpha >> 48) as usize & 15];
+
+    let mut block = [[0; 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let (x, y) = (i % 4, i / 4);
+        let sub = if flip { y >= 2 } else { x >= 2 } as usize;
+        let bit = pixel_bit(i) as u32;
+        let index = (field(16 + bit, 1) << 1 | field(bit, 1)) as usize;
+        let offset = COLOUR_TABLES[tables[sub] as usize][index];
+        for c in 0..3 {
+            pixel[c] = (bases[sub][c] + offset).clamp(0, 255) as u8;
+        }
+
+        let alpha_index = (alpha >> (45 - bit * 3)) as usize & 7;
+        pixel[3] = (base_alpha + alpha_table[alpha_index] * multiplier).clamp(0, 255) as u8;
+    }
+    block
+}
@@ -0,0 +1,250 @@
+use super::TextureFormat;
+
+/// The bytes every KTX 2.0 file starts with.
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// Sprite sheets hold colours, so every format is written as sRGB.
+fn vk_format(format: TextureFormat) -> u32 {
+    match format {
+        TextureFormat::Bc1 => 134,  // VK_FORMAT_BC1_RGBA_SRGB_BLOCK
+        TextureFormat::Bc3 => 138,  // VK_FORMAT_BC3_SRGB_BLOCK
+        TextureFormat::Bc7 => 146,  // VK_FORMAT_BC7_SRGB_BLOCK
+        TextureFormat::Etc2 => 152, // VK_FORMAT_ETC2_R8G8B8A8_SRGB_BLOCK
+    }
+}
+
+/// A sample in the data format descriptor; which bits of a block hold which channel.
+struct Sample {
+    bit_offset: u16,
+    bit_length: u8,
+    channel: u8,
+}
+
+/// Marks a sample as linear, which alpha always is, even in sRGB formats.
+const KHR_DF_SAMPLE_DATATYPE_LINEAR: u8 = 0x10;
+
+/// Marks the colour channels as already multiplied by alpha.
+const KHR_DF_FLAG_ALPHA_PREMULTIPLIED: u8 = 0x1;
+
+/// Build the basic data format descriptor, which describes how the blocks are laid out.
+fn data_format_descriptor(format: TextureFormat, premultiplied: bool) -> Vec<u8> {
+    // The colour model and the samples of each format, from the Khronos Data Format Specification
+    let (model, samples) = match format {
+        TextureFormat::Bc1 => (
+            128,
+            vec![Sample {
+                bit_offset: 0,
+                bit_length: 63,
+                channel: 1,
+            }],
+        ),
+        TextureFormat::Bc3 => (
+            130,
+            vec![
+                Sample {
+                    bit_offset: 0,
+                    bit_length: 63,
+                    channel: 15 | KHR_DF_SAMPLE_DATATYPE_LINEAR,
+                },
+                Sample {
+                    bit_offset: 64,
+                    bit_length: 63,
+                    channel: 0,
+                },
+            ],
+        ),
+        TextureFormat::Bc7 => (
+            134,
+            vec![Sample {
+                bit_offset: 0,
+                bit_length: 127,
+                channel: 0,
+            }],
+        ),
+        TextureFormat::Etc2 => (
+            161,
+            vec![
+                Sample {
+                    bit_offset: 0,
+                    bit_length: 63,
+                    channel: 15 | KHR_DF_SAMPLE_DATATYPE_LINEAR,
+                },
+                Sample {
+                    bit_offset: 64,
+                    bit_length: 63,
+                    channel: 2,
+                },
+            ],
+        ),
+    };
+
+    let block_size = 24 + 16 * samples.len() as u32;
+    let mut out = vec![];
+    out.extend((4 + block_size).to_le_bytes());
+    // Khronos vendor and the basic descriptor type
+    out.extend(0u32.to_le_bytes());
+    // Version 1.3 of the descriptor and its size
+    out.extend(2u16.to_le_bytes());
+    out.extend((block_size as u16).to_le_bytes());
+    // Colour model, BT.709 primaries, sRGB transfer function and whether alpha is premultiplied
+    let flags = if premultiplied {
+        KHR_DF_FLAG_ALPHA_PREMULTIPLIED
+    } else {
+        0
+    };
+    out.extend([model, 1, 2, flags]);
+    // 4x4 blocks
+    out.extend([3, 3, 0, 0]);
+    // The size of a block in the only plane
+    out.extend([format.block_bytes() as u8, 0, 0, 0, 0, 0, 0, 0]);
+
+    for sample in samples.iter() {
+        out.extend(sample.bit_offset.to_le_bytes());
+        out.extend([sample.bit_length, sample.channel]);
+        out.extend([0, 0, 0, 0]);
+        out.extend(0u32.to_le_bytes());
+        out.extend(u32::MAX.to_le_bytes());
+    }
+
+    out
+}
+
+/// Returns the number of bytes needed to pad `length` to a multiple of `alignment`.
+fn padding(length: usize, alignment: usize) -> usize {
+    length.next_multiple_of(alignment) - length
+}
+
+/// Build a KTX 2.0 file from compressed mip levels, largest first.
+pub fn write(
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+    premultiplied: bool,
+) -> Vec<u8> {
+    let dfd = data_format_descriptor(format, premultiplied);
+
+    let header_length = IDENTIFIER.len() + 9 * 4 + 4 * 4 + 2 * 8;
+    let level_index_length = levels.len() * 3 * 8;
+    let dfd_offset = header_length + level_index_length;
+
+    // Mip levels are stored smallest first, each aligned to the size of a block
+    let alignment = format.block_bytes();
+    let mut level_offsets = vec![0; levels.len()];
+    let mut offset = dfd_offset + dfd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        offset += padding(offset, alignment);
+        level_offsets[i] = offset;
+        offset += level.len();
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend(IDENTIFIER);
+    for value in [
+        vk_format(format),
+        1, // typeSize is 1 for block compressed formats
+        width,
+        height,
+        0, // pixelDepth
+        0, // layerCount
+        1, // faceCount
+        levels.len() as u32,
+        0, // supercompressionScheme
+    ] {
+        out.extend(value.to_le_bytes());
+    }
+
+    // The data format descriptor, with no key/value data or supercompression data
+    for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+        out.extend(value.to_le_bytes());
+    }
+    out.extend(0u64.to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+
+    for (level, &offset) in levels.iter().zip(level_offsets.iter()) {
+        out.extend((offset as u64).to_le_bytes());
+        out.extend((level.len() as u64).to_le_bytes());
+        out.extend((level.len() as u64).to_le_bytes());
+    }
+
+    out.extend(&dfd);
+
+    for (i, level) in levels.iter().enumerate().rev() {
+        out.resize(level_offsets[i], 0);
+        out.extend(level);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> usize {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn header_describes_the_levels() {
+        for (format, vk) in [
+            (TextureFormat::Bc1, 134),
+            (TextureFormat::Bc3, 138),
+            (TextureFormat::Bc7, 146),
+            (TextureFormat::Etc2, 152),
+        ] {
+            let block = format.block_bytes();
+            // 8x4, 4x4 and 4x4 blocks of 8x4, 4x2 and 2x1 pixels
+            let levels = vec![vec![1; 2 * block], vec![2; block], vec![3; block]];
+            for premultiplied in [false, true] {
+                let file = write(format, 8, 4, &levels, premultiplied);
+                let name = format.name();
+
+                assert_eq!(file[..12], IDENTIFIER, "{name}");
+                assert_eq!(u32_at(&file, 12), vk, "{name}");
+                assert_eq!(u32_at(&file, 16), 1, "{name} typeSize");
+                assert_eq!(u32_at(&file, 20), 8, "{name} width");
+                assert_eq!(u32_at(&file, 24), 4, "{name} height");
+                assert_eq!(u32_at(&file, 28), 0, "{name} depth");
+                assert_eq!(u32_at(&file, 32), 0, "{name} layers");
+                assert_eq!(u32_at(&file, 36), 1, "{name} faces");
+                assert_eq!(u32_at(&file, 40), 3, "{name} levels");
+                assert_eq!(u32_at(&file, 44), 0, "{name} supercompression");
+
+                // The descriptor follows the level index and starts with its own length
+                let dfd_offset = u32_at(&file, 48) as usize;
+                let dfd_length = u32_at(&file, 52) as usize;
+                assert_eq!(dfd_offset, 80 + 3 * 24, "{name}");
+                assert_eq!(u32_at(&file, dfd_offset) as usize, dfd_length, "{name}");
+                let flags = file[dfd_offset + 15];
+                assert_eq!(
+                    flags == KHR_DF_FLAG_ALPHA_PREMULTIPLIED,
+                    premultiplied,
+                    "{name}"
+                );
+                assert_eq!(file[dfd_offset + 20] as usize, block, "{name}");
+
+                // Every level is aligned to a block, stored after the descriptor, smallest first
+                let mut previous_end = file.len();
+                for (i, level) in levels.iter().enumerate() {
+                    let entry = 80 + i * 24;
+                    let offset = u64_at(&file, entry);
+                    assert_eq!(u64_at(&file, entry + 8), level.len(), "{name}");
+                    assert_eq!(u64_at(&file, entry + 16), level.len(), "{name}");
+                    assert_eq!(offset % block, 0, "{name} level {i} is misaligned");
+                    assert!(offset >= dfd_offset + dfd_length, "{name}");
+                    assert!(offset + level.len() <= previous_end, "{name}");
+                    assert_eq!(&file[offset..offset + level.len()], level, "{name}");
+                    previous_end = offset;
+                }
+                assert_eq!(u64_at(&file, 80) + levels[0].len(), file.len(), "{name}");
+            }
+        }
+    }
+}
@@ -0,0 +1,435 @@
+pub mod bc1;
+pub mod bc3;
+pub mod bc7;
+pub mod dds;
+pub mod etc2;
+pub mod ktx2;
+
+use clap::{Parser, ValueEnum};
+use image::RgbaImage;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::path::Path;
+
+/// The pixels of a 4x4 block, from left to right and then top to bottom.
+pub type Block = [[u8; 4]; 16];
+
+/// A GPU compressed texture format
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TextureFormat {
+    /// 4 bits per pixel with 1 bit alpha. Supported by every desktop GPU.
+    Bc1,
+    /// 8 bits per pixel with smooth alpha. Supported by every desktop GPU.
+    Bc3,
+    /// 8 bits per pixel with much better quality than BC3. Needs a DirectX 11 class GPU.
+    Bc7,
+    /// 8 bits per pixel with smooth alpha. Supported by every OpenGL ES 3 and Vulkan mobile GPU.
+    Etc2,
+}
+
+impl TextureFormat {
+    /// The name of the format as written to the sprite sheet JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureFormat::Bc1 => "bc1",
+            TextureFormat::Bc3 => "bc3",
+            TextureFormat::Bc7 => "bc7",
+            TextureFormat::Etc2 => "etc2",
+        }
+    }
+
+    /// The number of bytes each 4x4 block is compressed to.
+    pub fn block_bytes(&self) -> usize {
+        match self {
+            TextureFormat::Bc1 => 8,
+            TextureFormat::Bc3 | TextureFormat::Bc7 | TextureFormat::Etc2 => 16,
+        }
+    }
+
+    fn encode_block(&self, block: &Block) -> Vec<u8> {
+        match self {
+            TextureFormat::Bc1 => bc1::encode_block(block, true).to_vec(),
+            TextureFormat::Bc3 => bc3::encode_block(block).to_vec(),
+            TextureFormat::Bc7 => bc7::encode_block(block).to_vec(),
+            TextureFormat::Etc2 => etc2::encode_block(block).to_vec(),
+        }
+    }
+}
+
+/// The file format a compressed texture is written in
+#[derive(Parser, ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Container {
+    /// Khronos KTX 2.0, which works with every format.
+    Ktx2,
+    /// DirectDraw Surface with the DX10 header. Only works with the BC formats.
+    Dds,
+}
+
+impl Container {
+    /// Returns an error if the container can't hold textures of the given format.
+    pub fn check_format(&self, format: TextureFormat) -> Result<(), String> {
+        match self {
+            Container::Ktx2 => Ok(()),
+            Container::Dds => dds::dxgi_format(format).map(|_| ()),
+        }
+    }
+
+    /// The file extension used for the container.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Ktx2 => "ktx2",
+            Container::Dds => "dds",
+        }
+    }
+}
+
+/// Read a 4x4 block of an image. Blocks that hang off the edge repeat the last row or column.
+fn read_block(image: &RgbaImage, block_x: u32, block_y: u32) -> Block {
+    let mut block = [[0; 4]; 16];
+    for y in 0..4 {
+        for x in 0..4 {
+            let px = (block_x * 4 + x).min(image.width() - 1);
+            let py = (block_y * 4 + y).min(image.height() - 1);
+            block[(y * 4 + x) as usize] = image.get_pixel(px, py).0;
+        }
+    }
+    block
+}
+
+/// Compress an image, returning the blocks from left to right and then top to bottom.
+pub fn encode(image: &RgbaImage, format: TextureFormat) -> Vec<u8> {
+    if image.width() == 0 || image.height() == 0 {
+        return vec![];
+    }
+
+    let blocks_x = image.width().div_ceil(4);
+    let blocks_y = image.height().div_ceil(4);
+
+    (0..blocks_y)
+        .into_par_iter()
+        .map(|block_y| {
+            let mut row = Vec::with_capacity(blocks_x as usize * format.block_bytes());
+            for block_x in 0..blocks_x {
+                row.extend(format.encode_block(&read_block(image, block_x, block_y)));
+            }
+            row
+        })
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Write compressed mip levels, largest first, to a container file.
+/// `premultiplied` marks the colours as already multiplied by alpha.
+pub fn write(
+    path: &Path,
+    container: Container,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+    premultiplied: bool,
+) -> Result<(), String> {
+    let bytes = match container {
+        Container::Ktx2 => ktx2::write(format, width, height, levels, premultiplied),
+        Container::Dds => dds::write(format, width, height, levels, premultiplied)?,
+    };
+
+    std::fs::write(path, bytes).map_err(|e| format!("Unable to write {:?}: {}", path, e))
+}
+
+/// Returns the mean and the direction of greatest variance of a set of points, using power iteration.
+/// The direction is zero if all of the points are the same.
+fn principal_axis<const N: usize>(points: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let mut mean = [0.0; N];
+    for p in points.iter() {
+        for c in 0..N {
+            mean[c] += p[c] / points.len() as f32;
+        }
+    }
+
+    let mut covariance = [[0.0; N]; N];
+    for p in points.iter() {
+        for i in 0..N {
+            for j in 0..N {
+                covariance[i][j] += (p[i] - mean[i]) * (p[j] - mean[j]);
+            }
+        }
+    }
+
+    let mut axis = [1.0; N];
+    for _ in 0..8 {
+        let mut next = [0.0; N];
+        for i in 0..N {
+            for j in 0..N {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < f32::EPSILON {
+            return (mean, [0.0; N]);
+        }
+        axis = next.map(|v| v / length);
+    }
+
+    (mean, axis)
+}
+
+/// Returns the two ends of the range the points cover along their principal axis.
+fn endpoints<const N: usize>(points: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let (mean, axis) = principal_axis(points);
+
+    let project = |p: &[f32; N]| (0..N).map(|c| (p[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (min, max) = points
+        .iter()
+        .map(project)
+        .fold((f32::MAX, f32::MIN), |(min, max), t| {
+            (min.min(t), max.max(t))
+        });
+
+    let at = |t: f32| {
+        let mut p = [0.0; N];
+        for c in 0..N {
+            p[c] = (mean[c] + axis[c] * t).clamp(0.0, 255.0);
+        }
+        p
+    };
+
+    (at(min), at(max))
+}
+
+/// Find the two endpoints that best fit the points in a least squares sense, given how far along the line
+/// between the endpoints each point is. Returns `None` if every point is at the same spot on the line.
+fn least_squares_endpoints<const N: usize>(
+    points: &[[f32; N]],
+    weights: &[f32],
+) -> Option<([f32; N], [f32; N])> {
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let mut ax = [0.0; N];
+    let mut bx = [0.0; N];
+    for (p, &w) in points.iter().zip(weights.iter()) {
+        let a = 1.0 - w;
+        let b = w;
+        aa += a * a;
+        ab += a * b;
+        bb += b * b;
+        for c in 0..N {
+            ax[c] += a * p[c];
+            bx[c] += b * p[c];
+        }
+    }
+
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+
+    let mut start = [0.0; N];
+    let mut end = [0.0; N];
+    for c in 0..N {
+        start[c] = ((ax[c] * bb - bx[c] * ab) / determinant).clamp(0.0, 255.0);
+        end[c] = ((bx[c] * aa - ax[c] * ab) / determinant).clamp(0.0, 255.0);
+    }
+
+    Some((start, end))
+}
+
+/// Returns the squared distance between two colours.
+fn distance<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    (0..N).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+/// Returns the index of the palette entry closest to a colour, and the squared distance to it.
+fn nearest<const N: usize>(palette: &[[f32; N]], colour: &[f32; N]) -> (usize, f32) {
+    palette
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, distance(p, colour)))
+        .fold(
+            (0, f32::MAX),
+            |best, (i, d)| if d < best.1 { (i, d) } else { best },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Decompress an image encoded with `encode`.
+    fn decode(bytes: &[u8], format: TextureFormat, width: u32, height: u32) -> RgbaImage {
+        let blocks_x = width.div_ceil(4);
+        let mut image = RgbaImage::new(width, height);
+        for (i, bytes) in bytes.chunks(format.block_bytes()).enumerate() {
+            let block = match format {
+                TextureFormat::Bc1 => bc1::decode_block(bytes, true),
+                TextureFormat::Bc3 => bc3::decode_block(bytes),
+                TextureFormat::Bc7 => bc7::decode_block(bytes),
+                TextureFormat::Etc2 => etc2::decode_block(bytes),
+            };
+            let (block_x, block_y) = (i as u32 % blocks_x, i as u32 / blocks_x);
+            for (j, pixel) in block.iter().enumerate() {
+                let (x, y) = (block_x * 4 + j as u32 % 4, block_y * 4 + j as u32 / 4);
+                if x < width && y < height {
+                    image.put_pixel(x, y, Rgba(*pixel));
+                }
+            }
+        }
+        image
+    }
+
+    /// The peak signal to noise ratio of some channels of two images, in decibels.
+    fn psnr(a: &RgbaImage, b: &RgbaImage, channels: std::ops::Range<usize>) -> f64 {
+        let mut total = 0.0;
+        let mut count = 0;
+        for (a, b) in a.pixels().zip(b.pixels()) {
+            for c in channels.clone() {
+                total += (a[c] as f64 - b[c] as f64).powi(2);
+                count += 1;
+            }
+        }
+        let mse = total / count as f64;
+        if mse == 0.0 {
+            return f64::INFINITY;
+        }
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    /// A sprite-like image with smooth gradients, hard edges and an alpha ramp around a solid middle.
+    /// The size isn't a multiple of 4, so blocks hang off the edge.
+    fn test_image(opaque: bool) -> RgbaImage {
+        RgbaImage::from_fn(38, 30, |x, y| {
+            let edge = (x as i32 - 19).abs().max((y as i32 - 15).abs() * 19 / 15);
+            let alpha = match edge {
+                _ if opaque => 255,
+                0..=12 => 255,
+                13..=17 => 255 - (edge - 12) * 50,
+                _ => 0,
+            };
+            let stripe = if (x / 6 + y / 6) % 2 == 0 { 40 } else { 0 };
+            Rgba([
+                (x * 6 + stripe) as u8,
+                (y * 8) as u8,
+                (200 - x * 2 - y * 2) as u8,
+                alpha as u8,
+            ])
+        })
+    }
+
+    fn round_trip(format: TextureFormat, opaque: bool) -> (RgbaImage, RgbaImage) {
+        let image = test_image(opaque);
+        let bytes = encode(&image, format);
+        let blocks = image.width().div_ceil(4) * image.height().div_ceil(4);
+        assert_eq!(bytes.len(), blocks as usize * format.block_bytes());
+
+        let decoded = decode(&bytes, format, image.width(), image.height());
+        (image, decoded)
+    }
+
+    #[test]
+    fn compressed_formats_keep_enough_quality() {
+        // The colour is only compared where it's visible, as BC1 writes transparent pixels as black
+        for (format, min_colour, min_alpha) in [
+            (TextureFormat::Bc1, 32.0, 30.0),
+            (TextureFormat::Bc3, 32.0, 36.0),
+            (TextureFormat::Bc7, 32.0, 34.0),
+            (TextureFormat::Etc2, 32.0, 42.0),
+        ] {
+            let (image, decoded) = round_trip(format, false);
+            let visible = |image: &RgbaImage| {
+                let mut image = image.clone();
+                for (pixel, original) in image.pixels_mut().zip(test_image(false).pixels()) {
+                    if original[3] < 128 {
+                        *pixel = Rgba([0, 0, 0, pixel[3]]);
+                    }
+                }
+                image
+            };
+
+            let colour = psnr(&visible(&image), &visible(&decoded), 0..3);
+            let alpha = match format {
+                // BC1 alpha is a single bit, so only check the cutoff
+                TextureFormat::Bc1 => {
+                    let thresholded = RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                        let a = image.get_pixel(x, y)[3];
+                        Rgba([0, 0, 0, if a < 128 { 0 } else { 255 }])
+                    });
+                    psnr(&thresholded, &decoded, 3..4)
+                }
+                _ => psnr(&image, &decoded, 3..4),
+            };
+
+            assert!(
+                colour >= min_colour,
+                "{} colour PSNR is {:.1} dB",
+                format.name(),
+                colour
+            );
+            assert!(
+                alpha >= min_alpha,
+                "{} alpha PSNR is {:.1} dB",
+                format.name(),
+                alpha
+            );
+        }
+    }
+
+    #[test]
+    fn opaque_images_stay_exactly_opaque() {
+        for format in TextureFormat::value_variants() {
+            let (image, decoded) = round_trip(*format, true);
+            let colour = psnr(&image, &decoded, 0..3);
+            assert!(
+                colour >= 30.0,
+                "{} colour PSNR is {:.1} dB",
+                format.name(),
+                colour
+            );
+            assert!(
+                decoded.pixels().all(|p| p[3] == 255),
+                "{} made an opaque pixel translucent",
+                format.name()
+            );
+        }
+    }
+
+    #[test]
+    fn containers_mark_premultiplied_alpha() {
+        let path = std::env::temp_dir().join("asset-builder-premultiplied-texture");
+        let levels = [encode(&test_image(false), TextureFormat::Bc3)];
+        let u32_at = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        for premultiplied in [false, true] {
+            write(
+                &path,
+                Container::Ktx2,
+                TextureFormat::Bc3,
+                38,
+                30,
+                &levels,
+                premultiplied,
+            )
+            .unwrap();
+            let file = std::fs::read(&path).unwrap();
+            // The flags byte of the data format descriptor
+            let dfd = u32_at(&file, 48) as usize;
+            assert_eq!(file[dfd + 15], premultiplied as u8);
+
+            write(
+                &path,
+                Container::Dds,
+                TextureFormat::Bc3,
+                38,
+                30,
+                &levels,
+                premultiplied,
+            )
+            .unwrap();
+            let file = std::fs::read(&path).unwrap();
+            // The alpha mode at the end of the DX10 header, 2 being DDS_ALPHA_MODE_PREMULTIPLIED
+            assert_eq!(u32_at(&file, 144), if premultiplied { 2 } else { 0 });
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}